@@ -1,7 +1,15 @@
-use clap::{Parser, Subcommand};
+use clap::{Args, Parser, Subcommand};
 use log::{info, warn};
-use std::collections::HashMap;
-use std::process::{Child, Command};
+use std::collections::BTreeSet;
+use std::env;
+use std::fs::File;
+use std::path::{Component, Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+
+use crate::config::{CommandConfig, Config, Kind};
+use crate::keys;
+#[cfg(unix)]
+use crate::system;
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
@@ -13,33 +21,368 @@ pub struct Cli {
 #[derive(Subcommand)]
 pub enum Commands {
     /// Run the application with hot reloading
-    Run {
-        /// Optional path to config file
-        #[arg(short, long, default_value = "watchx.yaml")]
-        config: String,
-    },
-}
-
-// Execute a list of commands in sequence
-pub fn execute(commands: &[String], env: &HashMap<String, String>) -> Vec<Child> {
-    let mut children = Vec::new();
-
-    for command in commands {
-        let parts: Vec<&str> = command.split_whitespace().collect();
-        if let Some((program, args)) = parts.split_first() {
-            match Command::new(program).args(args).envs(env.clone()).spawn() {
-                Ok(child) => {
-                    info!("Started process {} (PID: {})", program, child.id());
-                    children.push(child);
-                }
-                Err(e) => {
-                    warn!("Failed to start process {}: {}", program, e);
-                }
+    Run(RunArgs),
+}
+
+#[derive(Args)]
+pub struct RunArgs {
+    /// Optional path to config file
+    #[arg(short, long, default_value = "watchx.yaml")]
+    pub config: String,
+
+    /// Override the global debounce in milliseconds
+    #[arg(long)]
+    pub debounce: Option<u64>,
+
+    /// Override the global settle delay in milliseconds
+    #[arg(long)]
+    pub delay: Option<u64>,
+}
+
+// What led to a command being started, handed to it through its environment and placeholders
+#[derive(Debug, Clone, Default)]
+pub struct Trigger {
+    /// Changed paths, made absolute
+    pub files: Vec<PathBuf>,
+    /// Why it started: file event kinds like "modify", or "start", "exit", "liveness", "dependency"
+    pub kinds: Vec<String>,
+    /// How many times the command was started before
+    pub restart_count: u32,
+}
+
+impl Trigger {
+    pub fn new(kind: &str) -> Self {
+        Trigger {
+            kinds: vec![kind.to_string()],
+            ..Trigger::default()
+        }
+    }
+
+    // Fold in another reason, keeping every path and kind once.
+    // Paths are made absolute, so they work from any working directory and compare equal however they were spelled.
+    pub fn add(&mut self, kind: &str, files: &[PathBuf]) {
+        if !self.kinds.iter().any(|k| k == kind) {
+            self.kinds.push(kind.to_string());
+        }
+        for file in files {
+            let file = absolute(file);
+            if !self.files.contains(&file) {
+                self.files.push(file);
+            }
+        }
+    }
+
+    fn files(&self) -> Vec<String> {
+        self.files
+            .iter()
+            .map(|file| file.to_string_lossy().into_owned())
+            .collect()
+    }
+
+    // Directories holding the changed files, e.g. Go packages
+    fn packages(&self) -> Vec<String> {
+        let dirs: BTreeSet<PathBuf> = self
+            .files
+            .iter()
+            .filter_map(|file| file.parent().map(Path::to_path_buf))
+            .collect();
+        dirs.iter().map(|dir| dir.to_string_lossy().into_owned()).collect()
+    }
+
+    // Variables describing the trigger, for the spawned process
+    fn env(&self) -> Vec<(&'static str, String)> {
+        let joined = env::join_paths(&self.files)
+            .map(|paths| paths.to_string_lossy().into_owned())
+            .unwrap_or_else(|_| self.files().join(" "));
+        vec![
+            ("WATCHX_CHANGED_FILES", joined),
+            ("WATCHX_EVENT_KIND", self.kinds.join(",")),
+            ("WATCHX_RESTART_COUNT", self.restart_count.to_string()),
+        ]
+    }
+
+    fn placeholders(&self) -> [(&'static str, Vec<String>); 2] {
+        [("{files}", self.files()), ("{pkgs}", self.packages())]
+    }
+}
+
+// Drop `.` segments and resolve `..` without touching the filesystem, as deleted files can't be canonicalized
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            other => normalized.push(other),
+        }
+    }
+    normalized
+}
+
+// A path relative to the working directory made absolute
+pub fn absolute(path: &Path) -> PathBuf {
+    let cwd = env::current_dir().unwrap_or_default();
+    normalize(&cwd.join(path))
+}
+
+// Expand placeholders in an argv: an argument that is just a placeholder becomes one argument per path
+fn expand_args(args: &[String], trigger: &Trigger) -> Vec<String> {
+    let placeholders = trigger.placeholders();
+    let mut expanded = Vec::new();
+    for arg in args {
+        if let Some((_, values)) = placeholders.iter().find(|(name, _)| arg == name) {
+            expanded.extend(values.iter().cloned());
+            continue;
+        }
+        let mut arg = arg.clone();
+        for (name, values) in &placeholders {
+            arg = arg.replace(name, &values.join(" "));
+        }
+        expanded.push(arg);
+    }
+    expanded
+}
+
+// Expand placeholders in a line handed to a shell, quoting every path
+fn expand_line(line: &str, trigger: &Trigger) -> String {
+    let mut line = line.to_string();
+    for (name, values) in trigger.placeholders() {
+        line = line.replace(name, &shell_words::join(values));
+    }
+    line
+}
+
+// Split a command line with POSIX quoting and escaping rules, or hand it to a shell as is
+fn split_line(line: &str, shell: Option<&str>, trigger: &Trigger) -> Result<Vec<String>, String> {
+    match shell {
+        Some(shell) => {
+            let mut argv = shell_words::split(shell)
+                .map_err(|e| format!("invalid shell \"{}\": {}", shell, e))?;
+            argv.push(expand_line(line, trigger));
+            Ok(argv)
+        }
+        None => shell_words::split(line)
+            .map(|parts| expand_args(&parts, trigger))
+            .map_err(|e| format!("invalid command \"{}\": {}", line, e)),
+    }
+}
+
+// Build the argv for a command: exact `args`, untouched by placeholders, or the command line split by `split_line`
+pub fn argv(
+    command: &CommandConfig,
+    shell: Option<&str>,
+    trigger: &Trigger,
+) -> Result<Vec<String>, String> {
+    match &command.args {
+        Some(args) => Ok(args.clone()),
+        None => split_line(&command.command, shell, trigger),
+    }
+}
+
+// While watchx reads the keyboard, typed input reaches commands through it.
+// Otherwise only the `stdin: true` command reads the terminal, directly.
+fn stdin_for(command: &CommandConfig) -> Stdio {
+    if keys::is_listening() {
+        Stdio::piped()
+    } else if command.stdin {
+        Stdio::inherit()
+    } else {
+        Stdio::null()
+    }
+}
+
+// Spawn argv in the command's working directory and environment.
+// `terminal` is the child's end of a pseudo-terminal, for commands with `tty: true`.
+fn spawn(
+    step: &str,
+    parts: Result<Vec<String>, String>,
+    command: &CommandConfig,
+    config: &Config,
+    trigger: &Trigger,
+    #[cfg_attr(windows, allow(unused_variables))] terminal: Option<File>,
+) -> Option<Child> {
+    let parts = match parts {
+        Ok(parts) => parts,
+        Err(e) => {
+            warn!("Failed to start {} {}: {}", step, command.name, e);
+            return None;
+        }
+    };
+    let Some((program, args)) = parts.split_first() else {
+        warn!("Invalid command: {}", command.command);
+        return None;
+    };
+
+    let mut process = Command::new(program);
+    process
+        .args(args)
+        .envs(command.merged_env(&config.env))
+        .envs(trigger.env());
+    if let Some(cwd) = &command.cwd {
+        process.current_dir(cwd);
+    }
+
+    // Output goes through the multiplexer, which labels every line with the command name.
+    // Run in a new process group so the whole tree can be signalled at once.
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        match terminal {
+            // The terminal is all of stdin, stdout and stderr. The child leads a session of its own,
+            // which makes it the leader of a new process group as well.
+            Some(terminal) => {
+                let streams = (terminal.try_clone(), terminal.try_clone());
+                let (Ok(stdin), Ok(stdout)) = streams else {
+                    warn!("Failed to start {} {}: cannot share its terminal", step, command.name);
+                    return None;
+                };
+                process.stdin(stdin).stdout(stdout).stderr(terminal);
+                unsafe { process.pre_exec(system::take_controlling_terminal) };
+            }
+            None => {
+                process.stdout(Stdio::piped()).stderr(Stdio::piped());
+                process.stdin(stdin_for(command)).process_group(0);
             }
-        } else {
-            warn!("Invalid command: {}", command);
         }
     }
+    #[cfg(windows)]
+    {
+        use std::os::windows::process::CommandExt;
+        const CREATE_NEW_PROCESS_GROUP: u32 = 0x0000_0200;
+        process.stdout(Stdio::piped()).stderr(Stdio::piped());
+        process.stdin(stdin_for(command));
+        process.creation_flags(CREATE_NEW_PROCESS_GROUP);
+    }
 
-    children
+    match process.spawn() {
+        Ok(child) => {
+            info!("Started {} {} (PID: {})", step, command.name, child.id());
+            Some(child)
+        }
+        Err(e) => {
+            warn!("Failed to start {} {}: {}", step, command.name, e);
+            None
+        }
+    }
+}
+
+// Start a single command, returning the child if it could be spawned
+pub fn execute(
+    command: &CommandConfig,
+    config: &Config,
+    trigger: &Trigger,
+    terminal: Option<File>,
+) -> Option<Child> {
+    let parts = argv(command, config.shell_for(command), trigger);
+    let step = match command.kind {
+        Kind::Service => "process",
+        Kind::Task => "task",
+    };
+    spawn(step, parts, command, config, trigger, terminal)
+}
+
+// Start the build step of a command, if it has one
+pub fn execute_build(
+    command: &CommandConfig,
+    config: &Config,
+    trigger: &Trigger,
+    terminal: Option<File>,
+) -> Option<Child> {
+    let build = command.build.as_ref()?;
+    let parts = split_line(build, config.shell_for(command), trigger);
+    spawn("build", parts, command, config, trigger, terminal)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::parse_config;
+
+    fn first_command(yaml: &str) -> CommandConfig {
+        parse_config(yaml).unwrap().commands.remove(0)
+    }
+
+    #[test]
+    fn test_argv_respects_quotes_and_escapes() {
+        let command = first_command(r#"commands: ['go run -ldflags "-X main.v=1" .']"#);
+        assert_eq!(
+            argv(&command, None, &Trigger::default()).unwrap(),
+            vec!["go", "run", "-ldflags", "-X main.v=1", "."]
+        );
+
+        let command = first_command(r#"commands: ['echo it\''s\ here']"#);
+        assert_eq!(argv(&command, None, &Trigger::default()).unwrap(), vec!["echo", "it's here"]);
+
+        let command = first_command(r#"commands: ['echo "unterminated']"#);
+        assert!(argv(&command, None, &Trigger::default()).is_err());
+    }
+
+    #[test]
+    fn test_argv_with_shell() {
+        let command = first_command(r#"commands: ["npm test && eslint src/*.js"]"#);
+        assert_eq!(
+            argv(&command, Some("bash -lc"), &Trigger::default()).unwrap(),
+            vec!["bash", "-lc", "npm test && eslint src/*.js"]
+        );
+    }
+
+    #[test]
+    fn test_argv_passes_args_through() {
+        let command = first_command(
+            r#"
+commands:
+  - args: ["printf", "%s $HOME", "a \"b\""]
+"#,
+        );
+        assert_eq!(
+            argv(&command, Some("sh -c"), &Trigger::default()).unwrap(),
+            vec!["printf", "%s $HOME", "a \"b\""]
+        );
+    }
+
+    #[test]
+    fn test_placeholders_expand_to_changed_paths() {
+        let mut trigger = Trigger::new("modify");
+        trigger.add("create", &[PathBuf::from("/src/a b.js"), PathBuf::from("/src/lib/c.js")]);
+
+        let command = first_command(r#"commands: ["eslint --fix {files}"]"#);
+        assert_eq!(
+            argv(&command, None, &trigger).unwrap(),
+            vec!["eslint", "--fix", "/src/a b.js", "/src/lib/c.js"]
+        );
+
+        let command = first_command(r#"commands: ["go test {pkgs}"]"#);
+        assert_eq!(
+            argv(&command, Some("sh -c"), &trigger).unwrap(),
+            vec!["sh", "-c", "go test /src /src/lib"]
+        );
+
+        let command = first_command(r#"commands: ["eslint {files}"]"#);
+        assert_eq!(
+            argv(&command, Some("sh -c"), &trigger).unwrap(),
+            vec!["sh", "-c", "eslint '/src/a b.js' /src/lib/c.js"]
+        );
+
+        // Nothing changed yet on the first start
+        assert_eq!(argv(&command, None, &Trigger::new("start")).unwrap(), vec!["eslint"]);
+
+        // `args` is the exact argv
+        let command = first_command(r#"commands: [{ args: ["echo", "{files}"] }]"#);
+        assert_eq!(argv(&command, None, &trigger).unwrap(), vec!["echo", "{files}"]);
+    }
+
+    #[test]
+    fn test_trigger_env() {
+        let mut trigger = Trigger::new("modify");
+        trigger.add("remove", &[PathBuf::from("/src/./old.rs")]);
+        trigger.add("modify", &[PathBuf::from("/src/old.rs")]);
+        trigger.restart_count = 3;
+
+        let env: std::collections::HashMap<_, _> = trigger.env().into_iter().collect();
+        assert_eq!(env["WATCHX_EVENT_KIND"], "modify,remove");
+        assert_eq!(env["WATCHX_RESTART_COUNT"], "3");
+        assert_eq!(env["WATCHX_CHANGED_FILES"], "/src/old.rs");
+    }
 }
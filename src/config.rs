@@ -1,17 +1,1394 @@
-use serde::Deserialize;
-use std::collections::HashMap;
+use serde::{Deserialize, Deserializer};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs;
+use std::time::Duration;
+
+use crate::logs;
 
 #[derive(Debug, Deserialize)]
 pub struct Config {
+    #[serde(default)]
     pub env: HashMap<String, String>,
-    pub commands: Vec<String>,
-    pub watch_dir: String,
+    #[serde(deserialize_with = "deserialize_commands")]
+    pub commands: Vec<CommandConfig>,
+    pub watch_dir: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_watch_dirs")]
+    pub watch_dirs: Vec<WatchDir>,
     pub ignore: Option<Vec<String>>,
+    /// Minimum time between restarts in milliseconds
+    pub debounce: Option<u64>,
+    /// Time without new writes before restarting, in milliseconds
+    pub delay: Option<u64>,
+    /// What to do when a port is held by a process watchx did not start
+    #[serde(default)]
+    pub port_policy: PortPolicy,
+    /// Free the ports of a command before starting it, defaults to true
+    pub reclaim_ports: Option<bool>,
+    /// Shell used to run command strings, e.g. "sh -c"
+    pub shell: Option<String>,
+    /// Where command output is kept on disk, `false` turns log files off
+    #[serde(default = "default_logs", deserialize_with = "deserialize_logs")]
+    pub logs: Option<Logs>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PortPolicy {
+    /// Kill whatever holds the port, even unrelated processes
+    Kill,
+    /// Wait for the other process to release the port
+    Wait,
+    /// Report the holder and do not start the command
+    #[default]
+    Fail,
+}
+
+pub const DEFAULT_DEBOUNCE_MS: u64 = 1000;
+pub const DEFAULT_DELAY_MS: u64 = 100;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct WatchDir {
+    pub path: String,
+    /// Watch subdirectories as well, defaults to true
+    #[serde(default = "default_recursive")]
+    pub recursive: bool,
+    /// Ignore patterns that only apply to this root
+    pub ignore: Option<Vec<String>>,
+}
+
+fn default_recursive() -> bool {
+    true
+}
+
+// A watch root is either a plain path or a detailed entry
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum WatchDirEntry {
+    Plain(String),
+    Detailed(WatchDir),
+}
+
+impl From<WatchDirEntry> for WatchDir {
+    fn from(entry: WatchDirEntry) -> Self {
+        match entry {
+            WatchDirEntry::Plain(path) => WatchDir {
+                path,
+                recursive: true,
+                ignore: None,
+            },
+            WatchDirEntry::Detailed(dir) => dir,
+        }
+    }
+}
+
+fn deserialize_watch_dirs<'de, D>(deserializer: D) -> Result<Vec<WatchDir>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let entries = Vec::<WatchDirEntry>::deserialize(deserializer)?;
+    Ok(entries.into_iter().map(WatchDir::from).collect())
+}
+
+pub const DEFAULT_LOG_DIR: &str = ".watchx/logs";
+pub const DEFAULT_LOG_MAX_SIZE: u64 = 10 * 1024 * 1024;
+pub const DEFAULT_LOG_KEEP: u32 = 3;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Logs {
+    /// Directory holding one `<name>.log` per command
+    pub dir: String,
+    /// Size in bytes at which a log file is rotated
+    pub max_size: u64,
+    /// Rotated files kept next to the current one, as `<name>.log.1` and up
+    pub keep: u32,
+}
+
+impl Default for Logs {
+    fn default() -> Self {
+        Logs {
+            dir: DEFAULT_LOG_DIR.to_string(),
+            max_size: DEFAULT_LOG_MAX_SIZE,
+            keep: DEFAULT_LOG_KEEP,
+        }
+    }
+}
+
+fn default_logs() -> Option<Logs> {
+    Some(Logs::default())
+}
+
+// A size in bytes, or with a unit like "10MB"
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Size {
+    Bytes(u64),
+    Text(String),
+}
+
+#[derive(Deserialize)]
+struct LogsSpec {
+    dir: Option<String>,
+    max_size: Option<Size>,
+    keep: Option<u32>,
+}
+
+// Log settings are either a switch or a detailed entry
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum LogsEntry {
+    Enabled(bool),
+    Detailed(LogsSpec),
+}
+
+// Logs are on unless turned off with `logs: false`
+fn deserialize_logs<'de, D>(deserializer: D) -> Result<Option<Logs>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let spec = match LogsEntry::deserialize(deserializer)? {
+        LogsEntry::Enabled(true) => return Ok(Some(Logs::default())),
+        LogsEntry::Enabled(false) => return Ok(None),
+        LogsEntry::Detailed(spec) => spec,
+    };
+    let max_size = match spec.max_size {
+        Some(Size::Bytes(bytes)) => bytes,
+        Some(Size::Text(text)) => parse_size(&text).map_err(serde::de::Error::custom)?,
+        None => DEFAULT_LOG_MAX_SIZE,
+    };
+    Ok(Some(Logs {
+        dir: spec.dir.unwrap_or_else(|| DEFAULT_LOG_DIR.to_string()),
+        max_size,
+        keep: spec.keep.unwrap_or(DEFAULT_LOG_KEEP),
+    }))
+}
+
+// Parse sizes like "512KB", "10MB" or "1GB", a bare number is in bytes
+fn parse_size(text: &str) -> Result<u64, String> {
+    let text = text.trim();
+    let split = text
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("invalid size \"{}\"", text))?;
+    let multiplier = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" => 1024,
+        "M" | "MB" => 1024 * 1024,
+        "G" | "GB" => 1024 * 1024 * 1024,
+        _ => return Err(format!("invalid size \"{}\", use B, KB, MB or GB", text)),
+    };
+    number
+        .checked_mul(multiplier)
+        .ok_or_else(|| format!("size \"{}\" is too large", text))
+}
+
+impl Config {
+    // All roots to watch, `watch_dir` first, defaulting to the current directory
+    pub fn watch_roots(&self) -> Vec<WatchDir> {
+        let mut roots = Vec::new();
+        if let Some(dir) = &self.watch_dir {
+            roots.push(WatchDirEntry::Plain(dir.clone()).into());
+        }
+        roots.extend(self.watch_dirs.iter().cloned());
+        if roots.is_empty() {
+            roots.push(WatchDirEntry::Plain("./".to_string()).into());
+        }
+        roots
+    }
+
+    pub fn debounce_for(&self, command: &CommandConfig) -> Duration {
+        let millis = command
+            .debounce
+            .or(self.debounce)
+            .unwrap_or(DEFAULT_DEBOUNCE_MS);
+        Duration::from_millis(millis)
+    }
+
+    pub fn shell_for<'a>(&'a self, command: &'a CommandConfig) -> Option<&'a str> {
+        command.shell.as_deref().or(self.shell.as_deref())
+    }
+
+    pub fn port_policy_for(&self, command: &CommandConfig) -> PortPolicy {
+        command.port_policy.unwrap_or(self.port_policy)
+    }
+
+    // Ports that should be freed before the command starts
+    pub fn reclaimed_ports<'a>(&self, command: &'a CommandConfig) -> &'a [u16] {
+        let reclaim = command.reclaim_ports.or(self.reclaim_ports).unwrap_or(true);
+        if reclaim {
+            &command.ports
+        } else {
+            &[]
+        }
+    }
+
+    pub fn delay_for(&self, command: &CommandConfig) -> Duration {
+        let millis = command.delay.or(self.delay).unwrap_or(DEFAULT_DELAY_MS);
+        Duration::from_millis(millis)
+    }
+
+    pub fn index_of(&self, name: &str) -> Option<usize> {
+        self.commands.iter().position(|command| command.name == name)
+    }
+
+    // Command indices with every dependency ahead of its dependents, otherwise in list order
+    pub fn start_order(&self) -> Vec<usize> {
+        let mut order: Vec<usize> = Vec::new();
+        while order.len() < self.commands.len() {
+            let next = (0..self.commands.len()).find(|index| {
+                !order.contains(index)
+                    && self.commands[*index].depends_on.iter().all(|dependency| {
+                        self.index_of(&dependency.name)
+                            .map(|dependency| order.contains(&dependency))
+                            .unwrap_or(true)
+                    })
+            });
+            // Cycles are rejected when the config is loaded
+            let Some(next) = next else { break };
+            order.push(next);
+        }
+        order
+    }
+
+    // The given commands and everything that depends on them, in start order
+    pub fn with_dependents(&self, targets: &[usize]) -> Vec<usize> {
+        let mut affected: HashSet<usize> = targets.iter().copied().collect();
+        let order = self.start_order();
+        for &index in &order {
+            let depends_on_affected = self.commands[index]
+                .depends_on
+                .iter()
+                .filter_map(|dependency| self.index_of(&dependency.name))
+                .any(|dependency| affected.contains(&dependency));
+            if depends_on_affected {
+                affected.insert(index);
+            }
+        }
+        order.into_iter().filter(|index| affected.contains(index)).collect()
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CommandConfig {
+    /// Display name, defaults to the program name
+    #[serde(default)]
+    pub name: String,
+    /// Long-running service or one-shot task
+    #[serde(default)]
+    pub kind: Kind,
+    /// Command line, split like a POSIX shell would unless a `shell` is set
+    #[serde(default, alias = "run")]
+    pub command: String,
+    /// Build step that must succeed before the command is (re)started
+    pub build: Option<String>,
+    /// Exact argv, passed through without any parsing
+    pub args: Option<Vec<String>>,
+    /// Overrides the global `shell` for this command
+    pub shell: Option<String>,
+    /// Working directory for the command
+    pub cwd: Option<String>,
+    /// Environment variables merged over the global `env`
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    /// Run the command and its build under a pseudo-terminal, so it keeps its colors and progress output
+    #[serde(default)]
+    pub tty: bool,
+    /// Send what is typed in the terminal to this command, only one command can have it
+    #[serde(default)]
+    pub stdin: bool,
+    /// Only changes matching these patterns restart the command
+    pub watch: Option<Vec<String>>,
+    /// Changes matching these patterns never restart the command
+    pub ignore: Option<Vec<String>>,
+    /// Overrides the global `debounce` for this command
+    pub debounce: Option<u64>,
+    /// Overrides the global `delay` for this command
+    pub delay: Option<u64>,
+    /// Signal sent to stop the command, defaults to TERM
+    pub stop_signal: Option<StopSignal>,
+    /// Time to wait for the command to exit before killing it, in milliseconds
+    pub stop_timeout: Option<u64>,
+    /// Ports the command listens on
+    #[serde(default)]
+    pub ports: Vec<u16>,
+    /// Overrides the global `port_policy` for this command
+    pub port_policy: Option<PortPolicy>,
+    /// Overrides the global `reclaim_ports` for this command
+    pub reclaim_ports: Option<bool>,
+    /// Check that tells when the command is up
+    pub ready: Option<ReadyCheck>,
+    /// Periodic check that restarts the command when it hangs
+    pub liveness: Option<LivenessCheck>,
+    /// Whether to restart the command when it exits on its own, defaults to never
+    #[serde(default)]
+    pub restart: RestartPolicy,
+    /// Restarts in a row before giving up until the next file change
+    pub max_restarts: Option<u32>,
+    /// Commands that must be up before this one starts, and whose restarts cascade to it
+    #[serde(default, deserialize_with = "deserialize_depends_on")]
+    pub depends_on: Vec<Dependency>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Dependency {
+    pub name: String,
+    /// What to wait for, when the config says so
+    pub condition: Option<Condition>,
+}
+
+impl Dependency {
+    // What to wait for: the condition given, or else what the dependency offers.
+    // A task is only useful once it is done, a command with a `ready` check once it is ready.
+    pub fn condition_for(&self, target: &CommandConfig) -> Condition {
+        match (self.condition, target.kind) {
+            (Some(condition), _) => condition,
+            (None, Kind::Task) => Condition::Completed,
+            (None, _) if target.ready.is_some() => Condition::Ready,
+            (None, _) => Condition::Started,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Condition {
+    /// The dependency has been spawned
+    #[serde(alias = "service_started")]
+    Started,
+    /// The dependency ran to completion and exited successfully
+    #[serde(alias = "service_completed_successfully")]
+    Completed,
+    /// The readiness check of the dependency passed
+    #[serde(alias = "service_healthy")]
+    Ready,
+}
+
+pub const DEFAULT_READY_TIMEOUT_MS: u64 = 30000;
+
+pub const DEFAULT_LIVENESS_INTERVAL_MS: u64 = 10000;
+pub const DEFAULT_LIVENESS_TIMEOUT_MS: u64 = 2000;
+pub const DEFAULT_LIVENESS_FAILURES: u32 = 3;
+
+// Every configured probe has to pass for the command to count as alive
+#[derive(Debug, Clone, Deserialize)]
+pub struct LivenessCheck {
+    /// TCP port on localhost that accepts connections
+    pub port: Option<u16>,
+    /// Local http:// URL that answers a GET with 2xx
+    pub http: Option<String>,
+    /// Time between checks in milliseconds
+    pub interval: Option<u64>,
+    /// Time a single check may take in milliseconds
+    pub timeout: Option<u64>,
+    /// Consecutive failed checks before the command is restarted
+    pub failures: Option<u32>,
+}
+
+impl LivenessCheck {
+    pub fn interval(&self) -> Duration {
+        Duration::from_millis(self.interval.unwrap_or(DEFAULT_LIVENESS_INTERVAL_MS))
+    }
+
+    pub fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout.unwrap_or(DEFAULT_LIVENESS_TIMEOUT_MS))
+    }
+
+    pub fn failures(&self) -> u32 {
+        self.failures.unwrap_or(DEFAULT_LIVENESS_FAILURES).max(1)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.port.is_none() && self.http.is_none() {
+            return Err("`liveness` needs at least one of `port` or `http`".to_string());
+        }
+        if let Some(url) = &self.http {
+            if !url.starts_with("http://") {
+                return Err(format!("`liveness.http` must be an http:// URL, got \"{}\"", url));
+            }
+        }
+        Ok(())
+    }
+}
+
+// Every configured probe has to pass for the command to be ready
+#[derive(Debug, Clone, Deserialize)]
+pub struct ReadyCheck {
+    /// TCP port on localhost that accepts connections
+    pub port: Option<u16>,
+    /// Local http:// URL that answers a GET with 2xx
+    pub http: Option<String>,
+    /// Regex matched against each line of stdout
+    pub log: Option<String>,
+    /// Give up after this many milliseconds
+    pub timeout: Option<u64>,
+}
+
+impl ReadyCheck {
+    pub fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout.unwrap_or(DEFAULT_READY_TIMEOUT_MS))
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.port.is_none() && self.http.is_none() && self.log.is_none() {
+            return Err("`ready` needs at least one of `port`, `http` or `log`".to_string());
+        }
+        if let Some(url) = &self.http {
+            if !url.starts_with("http://") {
+                return Err(format!("`ready.http` must be an http:// URL, got \"{}\"", url));
+            }
+        }
+        if let Some(pattern) = &self.log {
+            regex::Regex::new(pattern).map_err(|e| format!("invalid `ready.log` regex: {}", e))?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Deserialize)]
+struct DependencySpec {
+    #[serde(default)]
+    condition: Option<Condition>,
+}
+
+// Dependencies are either a list of names or a map of names to conditions, like compose
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum DependsOnEntry {
+    List(Vec<String>),
+    Map(BTreeMap<String, DependencySpec>),
+}
+
+fn deserialize_depends_on<'de, D>(deserializer: D) -> Result<Vec<Dependency>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let dependencies = match DependsOnEntry::deserialize(deserializer)? {
+        DependsOnEntry::List(names) => names
+            .into_iter()
+            .map(|name| Dependency {
+                name,
+                condition: None,
+            })
+            .collect(),
+        DependsOnEntry::Map(map) => map
+            .into_iter()
+            .map(|(name, spec)| Dependency {
+                name,
+                condition: spec.condition,
+            })
+            .collect(),
+    };
+    Ok(dependencies)
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+    /// Keeps running and is restarted on changes
+    #[default]
+    Service,
+    /// Runs to completion on each change and is never cut short
+    Task,
+}
+
+pub const DEFAULT_STOP_TIMEOUT_MS: u64 = 5000;
+pub const DEFAULT_MAX_RESTARTS: u32 = 5;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RestartPolicy {
+    /// Leave the command stopped until the next file change
+    #[default]
+    Never,
+    /// Restart when the command exits with an error or is killed by a signal
+    OnFailure,
+    /// Restart whenever the command exits
+    Always,
+}
+
+impl RestartPolicy {
+    pub fn applies_to(&self, success: bool) -> bool {
+        match self {
+            RestartPolicy::Never => false,
+            RestartPolicy::OnFailure => !success,
+            RestartPolicy::Always => true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+pub enum StopSignal {
+    #[serde(rename = "INT", alias = "SIGINT")]
+    Int,
+    #[default]
+    #[serde(rename = "TERM", alias = "SIGTERM")]
+    Term,
+    #[serde(rename = "HUP", alias = "SIGHUP")]
+    Hup,
+    #[serde(rename = "QUIT", alias = "SIGQUIT")]
+    Quit,
+}
+
+impl StopSignal {
+    // Signal name as understood by `kill -s`
+    pub fn name(&self) -> &'static str {
+        match self {
+            StopSignal::Int => "INT",
+            StopSignal::Term => "TERM",
+            StopSignal::Hup => "HUP",
+            StopSignal::Quit => "QUIT",
+        }
+    }
+
+    #[cfg(unix)]
+    pub fn number(&self) -> i32 {
+        match self {
+            StopSignal::Int => libc::SIGINT,
+            StopSignal::Term => libc::SIGTERM,
+            StopSignal::Hup => libc::SIGHUP,
+            StopSignal::Quit => libc::SIGQUIT,
+        }
+    }
+}
+
+// A command is either a plain string or a detailed entry
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum CommandEntry {
+    Plain(String),
+    Detailed(Box<CommandConfig>),
+}
+
+impl From<CommandEntry> for CommandConfig {
+    fn from(entry: CommandEntry) -> Self {
+        match entry {
+            CommandEntry::Plain(command) => CommandConfig {
+                name: String::new(),
+                kind: Kind::default(),
+                command,
+                build: None,
+                args: None,
+                shell: None,
+                cwd: None,
+                env: HashMap::new(),
+                tty: false,
+                stdin: false,
+                watch: None,
+                ignore: None,
+                debounce: None,
+                delay: None,
+                stop_signal: None,
+                stop_timeout: None,
+                ports: Vec::new(),
+                port_policy: None,
+                reclaim_ports: None,
+                ready: None,
+                liveness: None,
+                restart: RestartPolicy::default(),
+                max_restarts: None,
+                depends_on: Vec::new(),
+            },
+            CommandEntry::Detailed(config) => *config,
+        }
+    }
+}
+
+fn deserialize_commands<'de, D>(deserializer: D) -> Result<Vec<CommandConfig>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let entries = Vec::<CommandEntry>::deserialize(deserializer)?;
+    let commands: Vec<CommandConfig> = entries.into_iter().map(CommandConfig::from).collect();
+    for command in &commands {
+        let has_command = !command.command.trim().is_empty();
+        let has_args = command.args.as_ref().is_some_and(|args| !args.is_empty());
+        if has_command == has_args {
+            return Err(serde::de::Error::custom(
+                "each command needs exactly one of `command` or `args`",
+            ));
+        }
+        let service_only =
+            command.ready.is_some() || command.liveness.is_some() || command.restart != RestartPolicy::Never;
+        if command.kind == Kind::Task && service_only {
+            return Err(serde::de::Error::custom(
+                "`kind: task` commands run to completion and cannot have `ready`, `liveness` or `restart`",
+            ));
+        }
+        if let Some(ready) = &command.ready {
+            ready.validate().map_err(serde::de::Error::custom)?;
+        }
+        if let Some(liveness) = &command.liveness {
+            liveness.validate().map_err(serde::de::Error::custom)?;
+        }
+    }
+    if commands.iter().filter(|command| command.stdin).count() > 1 {
+        return Err(serde::de::Error::custom(
+            "only one command can have `stdin: true`, switch between them with the keyboard instead",
+        ));
+    }
+    Ok(commands)
+}
+
+impl CommandConfig {
+    // Short form of the command for logs and default names
+    pub fn program(&self) -> &str {
+        match &self.args {
+            Some(args) if !args.is_empty() => &args[0],
+            _ => self.command.split_whitespace().next().unwrap_or("command"),
+        }
+    }
+
+    pub fn stop_timeout(&self) -> Duration {
+        Duration::from_millis(self.stop_timeout.unwrap_or(DEFAULT_STOP_TIMEOUT_MS))
+    }
+
+    pub fn max_restarts(&self) -> u32 {
+        self.max_restarts.unwrap_or(DEFAULT_MAX_RESTARTS)
+    }
+
+    // Global environment with the command's own variables taking precedence
+    pub fn merged_env(&self, global: &HashMap<String, String>) -> HashMap<String, String> {
+        let mut env = global.clone();
+        env.extend(self.env.clone());
+        env
+    }
+}
+
+// Give every command a unique name. Names given in the config must be unique already,
+// commands without one are named after their program, numbered when that is taken.
+// Names are compared by their log file, so `api server` and `api_server` can't share one.
+fn assign_names(commands: &mut [CommandConfig]) -> Result<(), String> {
+    let mut taken: HashMap<String, String> = HashMap::new();
+    for command in commands.iter().filter(|command| !command.name.is_empty()) {
+        if let Some(other) = taken.insert(logs::file_name(&command.name), command.name.clone()) {
+            if other == command.name {
+                return Err(format!("more than one command is named `{}`", command.name));
+            }
+            return Err(format!(
+                "commands `{}` and `{}` would share the log file `{}.log`, rename one of them",
+                other,
+                command.name,
+                logs::file_name(&command.name)
+            ));
+        }
+    }
+    for command in commands.iter_mut().filter(|command| command.name.is_empty()) {
+        let program = command.program().to_string();
+        let mut name = program.clone();
+        let mut count = 1;
+        while taken.contains_key(&logs::file_name(&name)) {
+            count += 1;
+            name = format!("{}-{}", program, count);
+        }
+        taken.insert(logs::file_name(&name), name.clone());
+        command.name = name;
+    }
+    Ok(())
+}
+
+// Every dependency must name another command, and the graph must not loop
+fn check_dependencies(commands: &[CommandConfig]) -> Result<(), String> {
+    for command in commands {
+        for dependency in &command.depends_on {
+            if dependency.name == command.name {
+                return Err(format!("command `{}` depends on itself", command.name));
+            }
+            let Some(target) = commands.iter().find(|other| other.name == dependency.name) else {
+                return Err(format!(
+                    "command `{}` depends on unknown command `{}`",
+                    command.name, dependency.name
+                ));
+            };
+            // Waiting for a service to exit would hold up watchx for as long as it runs
+            if dependency.condition == Some(Condition::Completed) && target.kind == Kind::Service {
+                return Err(format!(
+                    "command `{}` waits for `{}` to complete, but `{}` is a service, make it `kind: task`",
+                    command.name, dependency.name, dependency.name
+                ));
+            }
+            if dependency.condition == Some(Condition::Ready) && target.ready.is_none() {
+                return Err(format!(
+                    "command `{}` waits for `{}` to be ready, but `{}` has no `ready` check",
+                    command.name, dependency.name, dependency.name
+                ));
+            }
+        }
+    }
+
+    // Depth-first search, keeping the current path to report the cycle
+    fn visit<'a>(
+        name: &'a str,
+        commands: &'a [CommandConfig],
+        path: &mut Vec<&'a str>,
+        done: &mut HashSet<&'a str>,
+    ) -> Result<(), String> {
+        if let Some(start) = path.iter().position(|entry| *entry == name) {
+            let mut cycle = path[start..].to_vec();
+            cycle.push(name);
+            return Err(format!("dependency cycle: {}", cycle.join(" -> ")));
+        }
+        if done.contains(name) {
+            return Ok(());
+        }
+        path.push(name);
+        if let Some(command) = commands.iter().find(|command| command.name == name) {
+            for dependency in &command.depends_on {
+                visit(&dependency.name, commands, path, done)?;
+            }
+        }
+        path.pop();
+        done.insert(name);
+        Ok(())
+    }
+
+    let mut done = HashSet::new();
+    for command in commands {
+        visit(&command.name, commands, &mut Vec::new(), &mut done)?;
+    }
+    Ok(())
+}
+
+pub fn parse_config(content: &str) -> Result<Config, serde_yaml::Error> {
+    let mut config: Config = serde_yaml::from_str(content)?;
+    assign_names(&mut config.commands).map_err(<serde_yaml::Error as serde::de::Error>::custom)?;
+    check_dependencies(&config.commands).map_err(<serde_yaml::Error as serde::de::Error>::custom)?;
+    Ok(config)
 }
 
 // Read and parse the configuration file
 pub fn read_config(path: &str) -> Config {
     let config_content = fs::read_to_string(path).expect("Failed to read configuration file");
-    serde_yaml::from_str(&config_content).expect("Failed to parse YAML configuration")
-}
\ No newline at end of file
+    parse_config(&config_content).expect("Failed to parse YAML configuration")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_plain_and_detailed_commands() {
+        let config = parse_config(
+            r#"
+env:
+  PORT: "8080"
+  MODE: "dev"
+commands:
+  - "go run main.go"
+  - name: web
+    command: "npm run dev"
+    cwd: "./frontend"
+    env:
+      PORT: "3000"
+watch_dir: "./"
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(config.commands.len(), 2);
+        assert_eq!(config.commands[0].name, "go");
+        assert_eq!(config.commands[0].command, "go run main.go");
+        assert!(config.commands[0].cwd.is_none());
+        assert_eq!(config.commands[1].name, "web");
+        assert_eq!(config.commands[1].cwd.as_deref(), Some("./frontend"));
+
+        let env = config.commands[1].merged_env(&config.env);
+        assert_eq!(env["PORT"], "3000");
+        assert_eq!(env["MODE"], "dev");
+    }
+
+    #[test]
+    fn test_duplicate_names_are_made_unique() {
+        let config = parse_config(
+            r#"
+commands:
+  - "npm run api"
+  - "npm run web"
+watch_dir: "./"
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(config.commands[0].name, "npm");
+        assert_eq!(config.commands[1].name, "npm-2");
+    }
+
+    #[test]
+    fn test_reject_duplicate_explicit_names() {
+        let duplicate = parse_config(
+            r#"
+commands:
+  - { name: api, command: "go run ./api" }
+  - { name: api, command: "go run ./admin" }
+"#,
+        )
+        .unwrap_err();
+        assert!(duplicate.to_string().contains("more than one command is named `api`"));
+
+        // Only derived names are numbered, an explicit name keeps its own
+        let config = parse_config(
+            r#"
+commands:
+  - "npm run web"
+  - { name: npm, command: "npm run api" }
+"#,
+        )
+        .unwrap();
+        assert_eq!(config.commands[0].name, "npm-2");
+        assert_eq!(config.commands[1].name, "npm");
+    }
+
+    #[test]
+    fn test_reject_names_sharing_a_log_file() {
+        let error = parse_config(
+            r#"
+commands:
+  - { name: api server, command: "go run ./api" }
+  - { name: api_server, command: "go run ./admin" }
+"#,
+        )
+        .unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("commands `api server` and `api_server` would share the log file `api_server.log`"));
+
+        // A derived name steps around an explicit one that maps to the same file
+        let config = parse_config(
+            r#"
+commands:
+  - "./run.sh web"
+  - { name: ._run.sh, command: "./run.sh api" }
+"#,
+        )
+        .unwrap();
+        assert_eq!(config.commands[0].name, "./run.sh-2");
+        assert_eq!(config.commands[1].name, "._run.sh");
+    }
+
+    #[test]
+    fn test_parse_watch_dirs() {
+        let config = parse_config(
+            r#"
+commands:
+  - "go run main.go"
+watch_dirs:
+  - "./src"
+  - path: "../proto"
+    recursive: false
+    ignore:
+      - "*.pb.go"
+"#,
+        )
+        .unwrap();
+
+        let roots = config.watch_roots();
+        assert_eq!(roots.len(), 2);
+        assert_eq!(roots[0].path, "./src");
+        assert!(roots[0].recursive);
+        assert_eq!(roots[1].path, "../proto");
+        assert!(!roots[1].recursive);
+        assert_eq!(roots[1].ignore, Some(vec![String::from("*.pb.go")]));
+    }
+
+    #[test]
+    fn test_watch_roots_default_to_current_dir() {
+        let config = parse_config("commands:\n  - \"cargo run\"\n").unwrap();
+        let roots = config.watch_roots();
+        assert_eq!(roots.len(), 1);
+        assert_eq!(roots[0].path, "./");
+    }
+
+    #[test]
+    fn test_parse_args_and_shell() {
+        let config = parse_config(
+            r#"
+shell: "sh -c"
+commands:
+  - name: api
+    args: ["go", "run", "-ldflags", "-X main.v=1", "."]
+  - command: "npm test | tee out.txt"
+    shell: "bash -lc"
+  - "cargo run"
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(config.commands[0].program(), "go");
+        assert_eq!(config.commands[1].name, "npm");
+        assert_eq!(config.shell_for(&config.commands[1]), Some("bash -lc"));
+        assert_eq!(config.shell_for(&config.commands[2]), Some("sh -c"));
+
+        assert!(parse_config("commands:\n  - name: empty\n").is_err());
+        assert!(parse_config("commands:\n  - command: \"a\"\n    args: [\"b\"]\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_tty() {
+        let config = parse_config("commands:\n  - command: cargo run\n    tty: true\n  - npm test\n").unwrap();
+        assert!(config.commands[0].tty && !config.commands[1].tty);
+    }
+
+    #[test]
+    fn test_parse_stdin() {
+        let config = parse_config("commands:\n  - command: rails c\n    stdin: true\n  - bin/dev\n").unwrap();
+        assert!(config.commands[0].stdin && !config.commands[1].stdin);
+        let error = parse_config("commands:\n  - command: a\n    stdin: true\n  - command: b\n    stdin: true\n")
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("only one command"), "{}", error);
+    }
+
+    #[test]
+    fn test_parse_build_and_run_steps() {
+        let config = parse_config(
+            r#"
+commands:
+  - name: api
+    build: "go build -o ./bin/api ."
+    run: "./bin/api"
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(config.commands[0].command, "./bin/api");
+        assert_eq!(config.commands[0].build.as_deref(), Some("go build -o ./bin/api ."));
+    }
+
+    #[test]
+    fn test_parse_stop_signal_and_timeout() {
+        let config = parse_config(
+            r#"
+commands:
+  - "cargo run"
+  - command: "node server.js"
+    stop_signal: INT
+    stop_timeout: 8000
+  - command: "python app.py"
+    stop_signal: SIGQUIT
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(config.commands[0].stop_signal.unwrap_or_default(), StopSignal::Term);
+        assert_eq!(config.commands[0].stop_timeout(), Duration::from_millis(5000));
+        assert_eq!(config.commands[1].stop_signal, Some(StopSignal::Int));
+        assert_eq!(config.commands[1].stop_timeout(), Duration::from_millis(8000));
+        assert_eq!(config.commands[2].stop_signal, Some(StopSignal::Quit));
+        assert!(parse_config("commands:\n  - command: \"x\"\n    stop_signal: USR1\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_port_policy() {
+        let config = parse_config("commands:\n  - \"go run .\"\n").unwrap();
+        assert_eq!(config.port_policy, PortPolicy::Fail);
+
+        let config = parse_config("commands:\n  - \"go run .\"\nport_policy: wait\n").unwrap();
+        assert_eq!(config.port_policy, PortPolicy::Wait);
+        assert!(parse_config("commands: []\nport_policy: nuke\n").is_err());
+    }
+
+    #[test]
+    fn test_ports_per_command() {
+        let config = parse_config(
+            r#"
+commands:
+  - name: api
+    command: "go run ."
+    ports: [8080]
+  - name: web
+    command: "npm run dev"
+    ports: [3000, 9229]
+    port_policy: kill
+  - name: lint
+    command: "eslint ."
+  - name: proxy
+    command: "caddy run"
+    ports: [443]
+    reclaim_ports: false
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(config.reclaimed_ports(&config.commands[0]), &[8080]);
+        assert_eq!(config.port_policy_for(&config.commands[0]), PortPolicy::Fail);
+        assert_eq!(config.reclaimed_ports(&config.commands[1]), &[3000, 9229]);
+        assert_eq!(config.port_policy_for(&config.commands[1]), PortPolicy::Kill);
+        assert!(config.reclaimed_ports(&config.commands[2]).is_empty());
+        assert!(config.reclaimed_ports(&config.commands[3]).is_empty());
+    }
+
+    #[test]
+    fn test_debounce_and_delay_overrides() {
+        let config = parse_config(
+            r#"
+commands:
+  - "cargo run"
+  - command: "npm run dev"
+    debounce: 100
+    delay: 50
+debounce: 300
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(config.debounce_for(&config.commands[0]), Duration::from_millis(300));
+        assert_eq!(config.delay_for(&config.commands[0]), Duration::from_millis(100));
+        assert_eq!(config.debounce_for(&config.commands[1]), Duration::from_millis(100));
+        assert_eq!(config.delay_for(&config.commands[1]), Duration::from_millis(50));
+    }
+
+    #[test]
+    fn test_parse_depends_on_forms() {
+        let config = parse_config(
+            r#"
+commands:
+  - name: migrate
+    kind: task
+    command: "diesel migration run"
+  - name: api
+    command: "cargo run"
+    depends_on:
+      migrate:
+        condition: completed
+  - name: e2e
+    command: "npx playwright test"
+    depends_on: [api]
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.commands[1].depends_on,
+            vec![Dependency {
+                name: "migrate".to_string(),
+                condition: Some(Condition::Completed)
+            }]
+        );
+        assert_eq!(config.commands[2].depends_on[0].condition, None);
+    }
+
+    #[test]
+    fn test_dependency_order_and_dependents() {
+        let config = parse_config(
+            r#"
+commands:
+  - name: e2e
+    command: "npx playwright test"
+    depends_on: [api]
+  - name: api
+    command: "cargo run"
+    depends_on: [migrate]
+  - name: web
+    command: "npm run dev"
+  - name: migrate
+    command: "diesel migration run"
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(config.start_order(), vec![2, 3, 1, 0]);
+        assert_eq!(config.with_dependents(&[3]), vec![3, 1, 0]);
+        assert_eq!(config.with_dependents(&[2]), vec![2]);
+    }
+
+    #[test]
+    fn test_reject_bad_dependencies() {
+        let cycle = parse_config(
+            r#"
+commands:
+  - name: a
+    command: "a"
+    depends_on: [c]
+  - name: b
+    command: "b"
+    depends_on: [a]
+  - name: c
+    command: "c"
+    depends_on: [b]
+"#,
+        )
+        .unwrap_err();
+        assert!(cycle.to_string().contains("dependency cycle: a -> c -> b -> a"));
+
+        let unknown = parse_config(
+            r#"
+commands:
+  - name: api
+    command: "cargo run"
+    depends_on: [db]
+"#,
+        )
+        .unwrap_err();
+        assert!(unknown.to_string().contains("unknown command `db`"));
+
+        let service = parse_config(
+            r#"
+commands:
+  - name: db
+    command: "postgres"
+  - name: api
+    command: "cargo run"
+    depends_on:
+      db:
+        condition: completed
+"#,
+        )
+        .unwrap_err();
+        assert!(service.to_string().contains("`db` is a service"));
+    }
+
+    #[test]
+    fn test_parse_ready_check() {
+        let config = parse_config(
+            r#"
+commands:
+  - name: api
+    command: "cargo run"
+    ready:
+      http: "http://localhost:8080/health"
+      timeout: 60000
+  - name: e2e
+    command: "npx playwright test"
+    depends_on:
+      api:
+        condition: ready
+"#,
+        )
+        .unwrap();
+
+        let ready = config.commands[0].ready.as_ref().unwrap();
+        assert_eq!(ready.http.as_deref(), Some("http://localhost:8080/health"));
+        assert_eq!(ready.timeout(), Duration::from_millis(60000));
+        assert_eq!(config.commands[1].depends_on[0].condition, Some(Condition::Ready));
+
+        // Without a condition, dependents wait for commands with a `ready` check to be ready
+        let config = parse_config(
+            r#"
+commands:
+  - name: api
+    command: "cargo run"
+    ready: { port: 8080 }
+  - name: worker
+    command: "cargo run --bin worker"
+  - name: e2e
+    command: "npx playwright test"
+    depends_on: [api, worker]
+  - name: smoke
+    command: "./smoke.sh"
+    depends_on:
+      api:
+        condition: started
+"#,
+        )
+        .unwrap();
+        let condition = |command: usize, dependency: usize| {
+            let dependency = &config.commands[command].depends_on[dependency];
+            dependency.condition_for(&config.commands[config.index_of(&dependency.name).unwrap()])
+        };
+        assert_eq!(condition(2, 0), Condition::Ready);
+        assert_eq!(condition(2, 1), Condition::Started);
+        assert_eq!(condition(3, 0), Condition::Started);
+    }
+
+    #[test]
+    fn test_dependency_conditions_on_tasks() {
+        let config = parse_config(
+            r#"
+commands:
+  - name: codegen
+    kind: task
+    command: "buf generate"
+  - name: api
+    command: "go run ."
+    depends_on: [codegen]
+  - name: docs
+    command: "mkdocs serve"
+    depends_on:
+      codegen:
+        condition: started
+"#,
+        )
+        .unwrap();
+        let codegen = &config.commands[0];
+        assert_eq!(config.commands[1].depends_on[0].condition_for(codegen), Condition::Completed);
+        assert_eq!(config.commands[2].depends_on[0].condition_for(codegen), Condition::Started);
+
+        let missing = parse_config(
+            r#"
+commands:
+  - name: api
+    command: "cargo run"
+  - name: e2e
+    command: "npx playwright test"
+    depends_on:
+      api:
+        condition: service_healthy
+"#,
+        )
+        .unwrap_err();
+        assert!(missing.to_string().contains("has no `ready` check"));
+
+        let bad_regex = parse_config(
+            r#"
+commands:
+  - command: "cargo run"
+    ready:
+      log: "Listening ("
+"#,
+        )
+        .unwrap_err();
+        assert!(bad_regex.to_string().contains("invalid `ready.log` regex"));
+    }
+
+    #[test]
+    fn test_parse_liveness_check() {
+        let config = parse_config(
+            r#"
+commands:
+  - name: api
+    command: "cargo run"
+    liveness:
+      http: "http://localhost:8080/health"
+      interval: 5000
+  - name: db
+    command: "postgres"
+    liveness:
+      port: 5432
+      failures: 5
+      timeout: 500
+"#,
+        )
+        .unwrap();
+
+        let api = config.commands[0].liveness.as_ref().unwrap();
+        assert_eq!(api.interval(), Duration::from_millis(5000));
+        assert_eq!(api.timeout(), Duration::from_millis(DEFAULT_LIVENESS_TIMEOUT_MS));
+        assert_eq!(api.failures(), DEFAULT_LIVENESS_FAILURES);
+        let db = config.commands[1].liveness.as_ref().unwrap();
+        assert_eq!(db.port, Some(5432));
+        assert_eq!(db.failures(), 5);
+
+        let empty = parse_config(
+            r#"
+commands:
+  - command: "cargo run"
+    liveness:
+      interval: 5000
+"#,
+        )
+        .unwrap_err();
+        assert!(empty.to_string().contains("`liveness` needs at least one of"));
+    }
+
+    #[test]
+    fn test_parse_restart_policy() {
+        let config = parse_config(
+            r#"
+commands:
+  - "go run main.go"
+  - command: "npm run dev"
+    restart: on-failure
+    max_restarts: 3
+  - command: "cargo run"
+    restart: always
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(config.commands[0].restart, RestartPolicy::Never);
+        assert_eq!(config.commands[0].max_restarts(), DEFAULT_MAX_RESTARTS);
+        assert_eq!(config.commands[1].restart, RestartPolicy::OnFailure);
+        assert_eq!(config.commands[1].max_restarts(), 3);
+        assert_eq!(config.commands[2].restart, RestartPolicy::Always);
+
+        assert!(!RestartPolicy::OnFailure.applies_to(true));
+        assert!(RestartPolicy::OnFailure.applies_to(false));
+        assert!(RestartPolicy::Always.applies_to(true));
+        assert!(!RestartPolicy::Never.applies_to(false));
+    }
+
+    #[test]
+    fn test_parse_task_kind() {
+        let config = parse_config(
+            r#"
+commands:
+  - name: test
+    kind: task
+    command: "cargo test"
+  - "cargo run"
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(config.commands[0].kind, Kind::Task);
+        assert_eq!(config.commands[1].kind, Kind::Service);
+
+        let error = parse_config(
+            r#"
+commands:
+  - kind: task
+    command: "eslint ."
+    restart: always
+"#,
+        )
+        .unwrap_err();
+        assert!(error.to_string().contains("cannot have `ready`, `liveness` or `restart`"));
+    }
+
+    #[test]
+    fn test_parse_command_watch_patterns() {
+        let config = parse_config(
+            r#"
+commands:
+  - name: api
+    command: "go run ."
+    watch: ["**/*.go", "go.mod"]
+    ignore: ["**/*_test.go"]
+  - "npm run dev"
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.commands[0].watch,
+            Some(vec!["**/*.go".to_string(), "go.mod".to_string()])
+        );
+        assert_eq!(config.commands[0].ignore, Some(vec!["**/*_test.go".to_string()]));
+        assert!(config.commands[1].watch.is_none());
+    }
+
+    #[test]
+    fn test_parse_logs() {
+        let config = parse_config("commands: [\"go run .\"]").unwrap();
+        assert_eq!(config.logs, Some(Logs::default()));
+
+        let config = parse_config("logs: false\ncommands: [\"go run .\"]").unwrap();
+        assert_eq!(config.logs, None);
+
+        let config = parse_config(
+            r#"
+logs:
+  dir: tmp/logs
+  max_size: 512KB
+  keep: 1
+commands: ["go run ."]
+"#,
+        )
+        .unwrap();
+        let logs = config.logs.unwrap();
+        assert_eq!(logs.dir, "tmp/logs");
+        assert_eq!(logs.max_size, 512 * 1024);
+        assert_eq!(logs.keep, 1);
+
+        assert_eq!(parse_size("1024"), Ok(1024));
+        assert_eq!(parse_size("10 mb"), Ok(10 * 1024 * 1024));
+        assert!(parse_size("10 parsecs").is_err());
+        assert!(parse_size("MB").is_err());
+
+        let error = parse_config("logs:\n  max_size: 10 parsecs\ncommands: [\"go run .\"]")
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("use B, KB, MB or GB"), "{}", error);
+    }
+}
@@ -0,0 +1,296 @@
+#[cfg(unix)]
+use log::warn;
+#[cfg(unix)]
+use std::io::{self, ErrorKind, IsTerminal, Read};
+use std::sync::atomic::{AtomicBool, Ordering};
+#[cfg(unix)]
+use std::sync::mpsc::channel;
+use std::sync::mpsc::Receiver;
+#[cfg(unix)]
+use std::thread;
+#[cfg(unix)]
+use std::time::Duration;
+
+#[cfg(unix)]
+use crate::system;
+
+// Set while watchx reads keys from the terminal, commands must not read stdin then
+static LISTENING: AtomicBool = AtomicBool::new(false);
+
+// Ctrl-], hands the keyboard back to watchx while typing into a command
+pub const DETACH: u8 = 0x1d;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    RestartAll,
+    /// Restart the command at this index
+    Restart(usize),
+    Pause,
+    Clear,
+    List,
+    Quit,
+    /// Type into the current input command
+    Attach,
+    /// Type into the next command
+    AttachNext,
+}
+
+pub const HELP: &str =
+    "r restart all, 1-9 restart one, p pause, c clear, l list, i type into a command, Tab next command, q quit";
+
+#[cfg_attr(windows, allow(dead_code))]
+fn parse(byte: u8) -> Option<Key> {
+    match byte {
+        b'r' | b'R' => Some(Key::RestartAll),
+        b'1'..=b'9' => Some(Key::Restart((byte - b'1') as usize)),
+        b'p' | b'P' => Some(Key::Pause),
+        b'c' | b'C' => Some(Key::Clear),
+        b'l' | b'L' => Some(Key::List),
+        b'q' | b'Q' => Some(Key::Quit),
+        b'i' | b'I' => Some(Key::Attach),
+        b'\t' => Some(Key::AttachNext),
+        _ => None,
+    }
+}
+
+// How the terminal is read, and who the input is for
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Mode {
+    /// Single keys control watchx
+    #[default]
+    Keys,
+    /// Whole lines, edited and echoed by the terminal, go to a command reading a pipe
+    Lines,
+    /// Every byte goes to a command on a pseudo-terminal as typed, which echoes it itself
+    Raw,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Event {
+    Key(Key),
+    /// Typed input for the attached command
+    Input(Vec<u8>),
+    /// Ctrl-D on an empty line, the input of the attached command ends
+    Eof,
+    /// Ctrl-] was pressed, keys control watchx again
+    Detach,
+}
+
+// Turns what was read from the terminal into events, by the mode at the time they are taken
+#[derive(Default)]
+#[cfg_attr(windows, allow(dead_code))]
+struct Decoder {
+    pending: Vec<u8>,
+    mode: Mode,
+}
+
+#[cfg_attr(windows, allow(dead_code))]
+impl Decoder {
+    fn next(&mut self) -> Option<Event> {
+        while !self.pending.is_empty() {
+            if self.mode == Mode::Keys {
+                if let Some(key) = parse(self.pending.remove(0)) {
+                    return Some(Event::Key(key));
+                }
+                continue;
+            }
+            let Some(end) = self.pending.iter().position(|byte| *byte == DETACH) else {
+                return Some(Event::Input(std::mem::take(&mut self.pending)));
+            };
+            if end == 0 {
+                self.pending.remove(0);
+                return Some(Event::Detach);
+            }
+            // A line still being typed is dropped, the command only ever sees whole lines
+            let end = match self.mode {
+                Mode::Lines => match self.pending[..end].iter().rposition(|byte| *byte == b'\n') {
+                    Some(newline) => newline + 1,
+                    None => {
+                        self.pending.drain(..end);
+                        continue;
+                    }
+                },
+                _ => end,
+            };
+            return Some(Event::Input(self.pending.drain(..end).collect()));
+        }
+        None
+    }
+}
+
+// Terminal settings for a mode, based on the ones watchx started with
+#[cfg(unix)]
+fn settings(saved: &libc::termios, mode: Mode) -> libc::termios {
+    let mut settings = *saved;
+    match mode {
+        // Ctrl-C still raises SIGINT
+        Mode::Keys => {
+            settings.c_lflag &= !(libc::ICANON | libc::ECHO);
+            settings.c_cc[libc::VMIN] = 1;
+            settings.c_cc[libc::VTIME] = 0;
+        }
+        // Ctrl-] ends a line too, so it arrives without waiting for Enter
+        Mode::Lines => {
+            settings.c_lflag |= libc::ICANON | libc::ECHO;
+            settings.c_cc[libc::VEOL] = DETACH;
+        }
+        // Ctrl-C, Ctrl-Z and friends are for the command
+        Mode::Raw => {
+            settings.c_lflag &= !(libc::ICANON | libc::ECHO | libc::ISIG | libc::IEXTEN);
+            settings.c_iflag &= !(libc::IXON | libc::ICRNL | libc::INLCR | libc::IGNCR);
+            settings.c_cc[libc::VMIN] = 1;
+            settings.c_cc[libc::VTIME] = 0;
+        }
+    }
+    settings
+}
+
+// The terminal watchx runs in. Its settings are restored when this is dropped.
+pub struct Keyboard {
+    /// What was read from the terminal, `None` when a read came back empty after Ctrl-D
+    chunks: Receiver<Option<Vec<u8>>>,
+    decoder: Decoder,
+    #[cfg(unix)]
+    saved: libc::termios,
+}
+
+#[cfg_attr(windows, allow(dead_code))]
+impl Keyboard {
+    // The next key or input, if any, without blocking
+    pub fn next(&mut self) -> Option<Event> {
+        loop {
+            if let Some(event) = self.decoder.next() {
+                return Some(event);
+            }
+            match self.chunks.try_recv() {
+                Ok(Some(bytes)) => self.decoder.pending.extend(bytes),
+                Ok(None) if self.decoder.mode == Mode::Lines => return Some(Event::Eof),
+                Ok(None) => {}
+                Err(_) => return None,
+            }
+        }
+    }
+
+    pub fn set_mode(&mut self, mode: Mode) {
+        #[cfg(unix)]
+        if let Err(e) = system::set_terminal_settings(&settings(&self.saved, mode)) {
+            warn!("Failed to set up the terminal: {}", e);
+        }
+        self.decoder.mode = mode;
+    }
+}
+
+impl Drop for Keyboard {
+    fn drop(&mut self) {
+        LISTENING.store(false, Ordering::SeqCst);
+        #[cfg(unix)]
+        let _ = system::set_terminal_settings(&self.saved);
+    }
+}
+
+// Whether watchx is reading keys from stdin
+pub fn is_listening() -> bool {
+    LISTENING.load(Ordering::SeqCst)
+}
+
+// Read keys from stdin on a background thread, when watchx runs in the foreground of a terminal
+#[cfg(unix)]
+pub fn listen() -> Option<Keyboard> {
+    if !io::stdin().is_terminal() || !system::is_foreground() {
+        return None;
+    }
+    let saved = match system::terminal_settings() {
+        Ok(saved) => saved,
+        Err(e) => {
+            warn!("Keyboard controls are off, cannot set up the terminal: {}", e);
+            return None;
+        }
+    };
+
+    let (tx, chunks) = channel();
+    thread::spawn(move || {
+        let mut stdin = io::stdin();
+        let mut buffer = [0u8; 1024];
+        loop {
+            // A read takes the terminal settings of when it started, so it only starts once there
+            // is input, by then read in the mode watchx switched to
+            match system::wait_for_input() {
+                Err(e) if e.kind() != ErrorKind::Interrupted => return,
+                _ => {}
+            }
+            let chunk = match stdin.read(&mut buffer) {
+                Ok(0) => {
+                    // Ctrl-D while reading lines, or the terminal went away and SIGHUP is on its way
+                    thread::sleep(Duration::from_millis(100));
+                    None
+                }
+                Ok(read) => Some(buffer[..read].to_vec()),
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(_) => return,
+            };
+            if tx.send(chunk).is_err() {
+                return;
+            }
+        }
+    });
+
+    let mut keyboard = Keyboard {
+        chunks,
+        decoder: Decoder::default(),
+        saved,
+    };
+    keyboard.set_mode(Mode::Keys);
+    LISTENING.store(true, Ordering::SeqCst);
+    Some(keyboard)
+}
+
+// Single keys need the console API, keyboard controls are Unix only for now
+#[cfg(windows)]
+pub fn listen() -> Option<Keyboard> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_keys() {
+        assert_eq!(parse(b'r'), Some(Key::RestartAll));
+        assert_eq!(parse(b'1'), Some(Key::Restart(0)));
+        assert_eq!(parse(b'9'), Some(Key::Restart(8)));
+        assert_eq!(parse(b'Q'), Some(Key::Quit));
+        assert_eq!(parse(b'\t'), Some(Key::AttachNext));
+        assert_eq!(parse(b'0'), None);
+        assert_eq!(parse(b'\n'), None);
+    }
+
+    fn decoder(mode: Mode, bytes: &[u8]) -> Decoder {
+        Decoder {
+            pending: bytes.to_vec(),
+            mode,
+        }
+    }
+
+    #[test]
+    fn test_decoder_forwards_input_until_detached() {
+        let mut keys = decoder(Mode::Raw, b"ls\r\x03\x1dr");
+        assert_eq!(keys.next(), Some(Event::Input(b"ls\r\x03".to_vec())));
+        assert_eq!(keys.next(), Some(Event::Detach));
+        // Whatever follows is read in the mode switched to meanwhile
+        keys.mode = Mode::Keys;
+        assert_eq!(keys.next(), Some(Event::Key(Key::RestartAll)));
+        assert_eq!(keys.next(), None);
+    }
+
+    #[test]
+    fn test_decoder_drops_unfinished_lines() {
+        let mut keys = decoder(Mode::Lines, b"puts 1\nput\x1d");
+        assert_eq!(keys.next(), Some(Event::Input(b"puts 1\n".to_vec())));
+        assert_eq!(keys.next(), Some(Event::Detach));
+
+        let mut keys = decoder(Mode::Lines, b"half\x1d");
+        assert_eq!(keys.next(), Some(Event::Detach));
+        assert_eq!(keys.next(), None);
+    }
+}
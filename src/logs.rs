@@ -0,0 +1,209 @@
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+
+use regex::Regex;
+
+use crate::command::{self, Trigger};
+use crate::config::Logs;
+use crate::output::Stream;
+
+// Changed files listed in a separator before the rest are summarized
+const SEPARATOR_FILES: usize = 10;
+
+// A command's log file, rotated once it grows past `max_size`
+struct LogFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_size: u64,
+    keep: u32,
+}
+
+impl LogFile {
+    fn open(path: PathBuf, max_size: u64, keep: u32) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(LogFile {
+            path,
+            file,
+            size,
+            max_size,
+            keep,
+        })
+    }
+
+    fn write(&mut self, text: &str) -> io::Result<()> {
+        if self.size > 0 && self.size + text.len() as u64 > self.max_size {
+            self.rotate()?;
+        }
+        self.file.write_all(text.as_bytes())?;
+        self.size += text.len() as u64;
+        Ok(())
+    }
+
+    // Shift `name.log.N` up by one, dropping the oldest, and start over with an empty file
+    fn rotate(&mut self) -> io::Result<()> {
+        if self.keep == 0 {
+            self.file = File::create(&self.path)?;
+            self.size = 0;
+            return Ok(());
+        }
+        let _ = fs::remove_file(rotated(&self.path, self.keep));
+        for number in (1..self.keep).rev() {
+            let from = rotated(&self.path, number);
+            if from.exists() {
+                fs::rename(&from, rotated(&self.path, number + 1))?;
+            }
+        }
+        fs::rename(&self.path, rotated(&self.path, 1))?;
+        self.file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+fn rotated(path: &Path, number: u32) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}", number));
+    PathBuf::from(name)
+}
+
+// Colors and cursor movement, which only make sense on a terminal
+fn strip_escapes(line: &str) -> std::borrow::Cow<'_, str> {
+    static ESCAPES: OnceLock<Regex> = OnceLock::new();
+    ESCAPES
+        .get_or_init(|| Regex::new(r"\x1b(\[[0-?]*[ -/]*[@-~]|\][^\x07\x1b]*(\x07|\x1b\\)|[@-_])").unwrap())
+        .replace_all(line, "")
+}
+
+fn timestamp() -> String {
+    chrono::Local::now().format("%Y-%m-%d %H:%M:%S%.3f").to_string()
+}
+
+// The log of a single command, shared by the output threads of its builds and runs
+#[derive(Clone)]
+pub struct Log(Arc<Mutex<LogFile>>);
+
+impl Log {
+    // Open `<dir>/<name>.log` for appending
+    pub fn open(settings: &Logs, name: &str) -> io::Result<Self> {
+        fs::create_dir_all(&settings.dir)?;
+        let path = Path::new(&settings.dir).join(format!("{}.log", file_name(name)));
+        LogFile::open(path, settings.max_size, settings.keep).map(|file| Log(Arc::new(Mutex::new(file))))
+    }
+
+    fn write(&self, text: &str) {
+        // A full disk should not take the command down with it, the terminal still shows everything
+        let _ = self.0.lock().unwrap().write(text);
+    }
+
+    // e.g. "2024-05-01 14:03:07.412 out  listening on :8080"
+    pub fn line(&self, stream: Stream, line: &str) {
+        let label = match stream {
+            Stream::Stdout => "out",
+            Stream::Stderr => "err",
+        };
+        self.write(&format!("{} {}  {}\n", timestamp(), label, strip_escapes(line)));
+    }
+
+    // A line of its own marking a build or start, and what caused it
+    pub fn separator(&self, step: &str, trigger: &Trigger) {
+        self.write(&format!("{}\n", separator(&timestamp(), step, trigger)));
+    }
+
+    // Something watchx noticed about the command, like how it exited
+    pub fn note(&self, text: &str) {
+        self.write(&format!("{} --   {}\n", timestamp(), text));
+    }
+}
+
+// Command names may contain characters that don't belong in a file name
+pub fn file_name(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_alphanumeric() || "-_.".contains(c) { c } else { '_' })
+        .collect()
+}
+
+// e.g. "==== 2024-05-01 14:03:07.412 start #3 (modify): src/main.rs ===="
+fn separator(timestamp: &str, step: &str, trigger: &Trigger) -> String {
+    let mut text = format!("==== {} {}", timestamp, step);
+    if !trigger.kinds.is_empty() {
+        text.push_str(&format!(" ({})", trigger.kinds.join(", ")));
+    }
+    if !trigger.files.is_empty() {
+        // Relative to where watchx runs, like the paths in the config
+        let cwd = env::current_dir().unwrap_or_default();
+        let files: Vec<String> = trigger
+            .files
+            .iter()
+            .take(SEPARATOR_FILES)
+            .map(|file| {
+                let path = command::absolute(file);
+                path.strip_prefix(&cwd).unwrap_or(&path).display().to_string()
+            })
+            .collect();
+        text.push_str(&format!(": {}", files.join(", ")));
+        if trigger.files.len() > SEPARATOR_FILES {
+            text.push_str(&format!(" and {} more", trigger.files.len() - SEPARATOR_FILES));
+        }
+    }
+    text.push_str(" ====");
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(dir: &Path, max_size: u64, keep: u32) -> Logs {
+        Logs {
+            dir: dir.to_string_lossy().into_owned(),
+            max_size,
+            keep,
+        }
+    }
+
+    #[test]
+    fn test_log_rotates_and_keeps_old_files() {
+        let dir = std::env::temp_dir().join(format!("watchx-logs-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+
+        let log = Log::open(&settings(&dir, 100, 2), "api server").unwrap();
+        for round in 0..4 {
+            log.line(Stream::Stdout, &format!("round {} {}", round, "x".repeat(50)));
+        }
+
+        let current = dir.join("api_server.log");
+        let read = |path: PathBuf| fs::read_to_string(path).unwrap();
+        assert!(read(current.clone()).contains("round 3"));
+        assert!(read(rotated(&current, 1)).contains("round 2"));
+        assert!(read(rotated(&current, 2)).contains("round 1"));
+        assert!(!rotated(&current, 3).exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_strip_escapes() {
+        assert_eq!(strip_escapes("\x1b[1;32m   Compiling\x1b[0m app"), "   Compiling app");
+        assert_eq!(strip_escapes("\x1b]0;title\x07\x1b[2Kdone"), "done");
+        assert_eq!(strip_escapes("plain"), "plain");
+    }
+
+    #[test]
+    fn test_separator_notes_the_trigger() {
+        let mut trigger = Trigger::new("modify");
+        trigger.add("create", &[PathBuf::from("src/a.rs"), PathBuf::from("src/b.rs")]);
+        assert_eq!(
+            separator("12:00:00", "start #2", &trigger),
+            "==== 12:00:00 start #2 (modify, create): src/a.rs, src/b.rs ===="
+        );
+
+        let files: Vec<PathBuf> = (0..12).map(|n| PathBuf::from(format!("{}.rs", n))).collect();
+        trigger.add("modify", &files);
+        assert!(separator("12:00:00", "build", &trigger).ends_with("7.rs and 4 more ===="));
+    }
+}
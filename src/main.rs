@@ -6,8 +6,15 @@ use colored::*;
 
 mod command;
 mod config;
+mod keys;
+mod logs;
 mod watcher;
+mod output;
+mod probes;
 mod processes;
+mod signals;
+#[cfg(unix)]
+mod system;
 
 fn main() -> Result<()> {
     // Initialize logger with custom format and colors
@@ -42,9 +49,9 @@ fn main() -> Result<()> {
 
     let cli = command::Cli::parse();
 
-    match cli.command {
-        command::Commands::Run { config } => watcher::run(&config)?,
-    }
+    let exit_code = match cli.command {
+        command::Commands::Run(args) => watcher::run(&args)?,
+    };
 
-    Ok(())
+    std::process::exit(exit_code)
 }
\ No newline at end of file
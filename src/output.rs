@@ -0,0 +1,290 @@
+use colored::*;
+use std::fs::File;
+use std::io::{self, ErrorKind, Read, Write};
+use std::process::Child;
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::Duration;
+
+use crate::config::Config;
+use crate::logs::Log;
+use crate::probes::LogMatcher;
+
+// Prefix colors, cycled through in command order
+const COLORS: [Color; 6] = [
+    Color::Cyan,
+    Color::Magenta,
+    Color::Yellow,
+    Color::Blue,
+    Color::Green,
+    Color::BrightCyan,
+];
+
+// A line without its newline yet is shown once the command has been quiet for this long
+const PARTIAL_LINE_TIMEOUT: Duration = Duration::from_millis(100);
+
+// How long to wait for the rest of the output of a child that has exited,
+// its own children may hold the pipes open for much longer
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+// Colored `[name]` label, padded to the longest command name
+#[derive(Debug, Clone)]
+pub struct Prefix {
+    name: String,
+    color: Color,
+    width: usize,
+}
+
+impl Prefix {
+    pub fn new(config: &Config, index: usize) -> Self {
+        let longest = config
+            .commands
+            .iter()
+            .map(|command| command.name.chars().count())
+            .max()
+            .unwrap_or(0);
+        Prefix {
+            name: config.commands[index].name.clone(),
+            color: COLORS[index % COLORS.len()],
+            width: longest + 2,
+        }
+    }
+
+    // Screen columns taken up in front of every line
+    pub fn columns(&self) -> usize {
+        self.width + 3
+    }
+
+    // e.g. "[api]   " for stdout and "[api] ! " for stderr
+    fn format(&self, stream: Stream) -> String {
+        let label = format!("{:<width$}", format!("[{}]", self.name), width = self.width);
+        let marker = match stream {
+            Stream::Stdout => " ".normal(),
+            Stream::Stderr => "!".red().bold(),
+        };
+        format!("{} {} ", label.color(self.color).bold(), marker)
+    }
+}
+
+// Splits a byte stream into lines, holding back whatever has no newline yet
+#[derive(Default)]
+struct LineBuffer {
+    pending: Vec<u8>,
+}
+
+impl LineBuffer {
+    // Complete lines from the bytes read so far
+    fn push(&mut self, bytes: &[u8]) -> Vec<String> {
+        self.pending.extend_from_slice(bytes);
+        let mut lines = Vec::new();
+        while let Some(end) = self.pending.iter().position(|byte| *byte == b'\n') {
+            let line: Vec<u8> = self.pending.drain(..=end).collect();
+            lines.push(decode(&line));
+        }
+        // Progress bars redraw their line after a carriage return, only the latest drawing is kept.
+        // A carriage return at the very end may still be followed by its newline.
+        let redrawn = self.pending[..self.pending.len().saturating_sub(1)]
+            .iter()
+            .rposition(|byte| *byte == b'\r');
+        if let Some(end) = redrawn {
+            self.pending.drain(..=end);
+        }
+        lines
+    }
+
+    // The partial line, minus a multi-byte character that is cut off at the end
+    fn flush_partial(&mut self) -> Option<String> {
+        let keep = match std::str::from_utf8(&self.pending) {
+            Err(e) if e.error_len().is_none() => self.pending.len() - e.valid_up_to(),
+            _ => 0,
+        };
+        let end = self.pending.len() - keep;
+        if end == 0 {
+            return None;
+        }
+        let line: Vec<u8> = self.pending.drain(..end).collect();
+        Some(decode(&line))
+    }
+
+    // Everything left once the stream is closed
+    fn flush(&mut self) -> Option<String> {
+        if self.pending.is_empty() {
+            return None;
+        }
+        let line = decode(&self.pending);
+        self.pending.clear();
+        Some(line)
+    }
+}
+
+// Lossy UTF-8 without the line ending, and without whatever a carriage return drew over
+fn decode(bytes: &[u8]) -> String {
+    let text = String::from_utf8_lossy(bytes);
+    let line = text.trim_end_matches(['\n', '\r']);
+    line.rsplit('\r').next().unwrap_or(line).to_string()
+}
+
+fn emit(prefix: &Prefix, stream: Stream, line: &str, matcher: Option<&LogMatcher>, log: Option<&Log>) {
+    if stream == Stream::Stdout {
+        if let Some(matcher) = matcher {
+            matcher.check(line);
+        }
+    }
+    if let Some(log) = log {
+        log.line(stream, line);
+    }
+
+    // Whole lines are written under the lock so commands never interleave mid-line.
+    // Colors a command leaves on are reset so they don't bleed into the next prefix.
+    let reset = if line.contains('\x1b') { "\x1b[0m" } else { "" };
+    let text = format!("{}{}{}\n", prefix.format(stream), line, reset);
+    let _ = match stream {
+        Stream::Stdout => io::stdout().lock().write_all(text.as_bytes()),
+        Stream::Stderr => io::stderr().lock().write_all(text.as_bytes()),
+    };
+}
+
+// Clear the screen and its scrollback, and move the cursor home
+pub fn clear_screen() {
+    let mut stdout = io::stdout().lock();
+    let _ = stdout.write_all(b"\x1b[2J\x1b[3J\x1b[H");
+    let _ = stdout.flush();
+}
+
+// Read a pipe until it closes, `None` marks the end
+fn read_pipe(mut pipe: impl Read, stream: Stream, tx: Sender<(Stream, Option<Vec<u8>>)>) {
+    let mut buffer = [0u8; 8192];
+    loop {
+        match pipe.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => {
+                if tx.send((stream, Some(buffer[..read].to_vec()))).is_err() {
+                    return;
+                }
+            }
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(_) => break,
+        }
+    }
+    let _ = tx.send((stream, None));
+}
+
+// Signals once every line of a child's output has been written
+pub struct Drained(Receiver<()>);
+
+impl Drained {
+    // Wait for the output of an exited child, so whatever is reported next comes after it
+    pub fn wait(&self) {
+        let _ = self.0.recv_timeout(DRAIN_TIMEOUT);
+    }
+}
+
+// Print the piped stdout and stderr of a child line by line under its prefix.
+// A child running under a pseudo-terminal writes both to `terminal` instead, read as stdout.
+// `matcher` sees every stdout line, for `ready.log` checks, and `log` keeps every line on disk.
+pub fn attach(
+    child: &mut Child,
+    terminal: Option<File>,
+    prefix: Prefix,
+    matcher: Option<LogMatcher>,
+    log: Option<Log>,
+) -> Drained {
+    let (tx, rx) = channel();
+    if let Some(terminal) = terminal {
+        // Reading fails with EIO once every process on the terminal has closed it
+        let tx = tx.clone();
+        thread::spawn(move || read_pipe(terminal, Stream::Stdout, tx));
+    }
+    if let Some(stdout) = child.stdout.take() {
+        let tx = tx.clone();
+        thread::spawn(move || read_pipe(stdout, Stream::Stdout, tx));
+    }
+    if let Some(stderr) = child.stderr.take() {
+        let tx = tx.clone();
+        thread::spawn(move || read_pipe(stderr, Stream::Stderr, tx));
+    }
+    drop(tx);
+
+    // Dropped when the thread ends, which wakes up `Drained::wait`
+    let (done, drained) = channel();
+    thread::spawn(move || {
+        let _done = done;
+        let mut stdout = LineBuffer::default();
+        let mut stderr = LineBuffer::default();
+        loop {
+            match rx.recv_timeout(PARTIAL_LINE_TIMEOUT) {
+                Ok((stream, bytes)) => {
+                    let buffer = match stream {
+                        Stream::Stdout => &mut stdout,
+                        Stream::Stderr => &mut stderr,
+                    };
+                    let lines = match bytes {
+                        Some(bytes) => buffer.push(&bytes),
+                        None => buffer.flush().into_iter().collect(),
+                    };
+                    for line in lines {
+                        emit(&prefix, stream, &line, matcher.as_ref(), log.as_ref());
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    // Prompts and progress output don't end with a newline
+                    if let Some(line) = stdout.flush_partial() {
+                        emit(&prefix, Stream::Stdout, &line, matcher.as_ref(), log.as_ref());
+                    }
+                    if let Some(line) = stderr.flush_partial() {
+                        emit(&prefix, Stream::Stderr, &line, None, log.as_ref());
+                    }
+                }
+                Err(RecvTimeoutError::Disconnected) => return,
+            }
+        }
+    });
+    Drained(drained)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_line_buffer_splits_lines_and_keeps_partials() {
+        let mut buffer = LineBuffer::default();
+        assert_eq!(buffer.push(b"one\r\ntwo\nthr"), vec!["one", "two"]);
+        assert_eq!(buffer.push(b"ee\n"), vec!["three"]);
+        assert!(buffer.push(b"Name: ").is_empty());
+        assert_eq!(buffer.flush_partial(), Some("Name: ".to_string()));
+        assert_eq!(buffer.flush(), None);
+    }
+
+    #[test]
+    fn test_line_buffer_handles_invalid_and_split_utf8() {
+        let mut buffer = LineBuffer::default();
+        assert_eq!(buffer.push(b"bad \xff byte\n"), vec!["bad \u{fffd} byte"]);
+
+        // "é" is two bytes, the second one arrives later
+        assert!(buffer.push(b"caf\xc3").is_empty());
+        assert_eq!(buffer.flush_partial(), Some("caf".to_string()));
+        assert_eq!(buffer.push(b"\xa9\n"), vec!["é"]);
+
+        assert!(buffer.push(b"\xc3").is_empty());
+        assert_eq!(buffer.flush(), Some("\u{fffd}".to_string()));
+    }
+
+    #[test]
+    fn test_line_buffer_keeps_the_latest_redraw() {
+        let mut buffer = LineBuffer::default();
+        assert!(buffer.push(b"[=>   ] 1/4\r[==>  ] 2/4\r").is_empty());
+        assert_eq!(buffer.pending, b"[==>  ] 2/4\r");
+        assert_eq!(buffer.push(b"[====>] 4/4\r\ndone\r\n"), vec!["[====>] 4/4", "done"]);
+
+        // Terminals end lines with \r\n, which may arrive in separate reads
+        assert!(buffer.push(b"ok\r").is_empty());
+        assert_eq!(buffer.push(b"\n"), vec!["ok"]);
+    }
+}
@@ -0,0 +1,376 @@
+use log::{info, warn};
+use regex::Regex;
+use std::io::{BufRead, BufReader, ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::config::{LivenessCheck, ReadyCheck};
+
+// Upper bound for a single connection attempt or request
+const ATTEMPT_TIMEOUT: Duration = Duration::from_secs(1);
+const PROBE_INTERVAL: Duration = Duration::from_millis(200);
+
+// Every address a host resolves to, so "localhost" covers both 127.0.0.1 and ::1
+fn resolve(host: &str, port: u16) -> Result<Vec<SocketAddr>, String> {
+    let addrs: Vec<SocketAddr> = (host, port)
+        .to_socket_addrs()
+        .map_err(|e| format!("cannot resolve {}: {}", host, e))?
+        .collect();
+    if addrs.is_empty() {
+        return Err(format!("cannot resolve {}", host));
+    }
+    Ok(addrs)
+}
+
+fn connect(host: &str, port: u16, timeout: Duration) -> Result<TcpStream, String> {
+    let mut last_error = String::new();
+    for addr in resolve(host, port)? {
+        match TcpStream::connect_timeout(&addr, timeout) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_error = format!("{}:{} {}", host, port, e),
+        }
+    }
+    Err(last_error)
+}
+
+// Whether something accepts connections on the local port
+pub fn tcp(port: u16, timeout: Duration) -> Result<(), String> {
+    connect("localhost", port, timeout).map(|_| ())
+}
+
+// Split an http:// URL into host, port and path
+fn parse_http_url(url: &str) -> Option<(String, u16, String)> {
+    let rest = url.strip_prefix("http://")?;
+    let (authority, path) = match rest.find('/') {
+        Some(slash) => (&rest[..slash], &rest[slash..]),
+        None => (rest, "/"),
+    };
+    // IPv6 hosts are bracketed, e.g. [::1]:3000
+    let (host, port) = match authority.strip_prefix('[') {
+        Some(bracketed) => {
+            let (host, rest) = bracketed.split_once(']')?;
+            (host, rest.strip_prefix(':'))
+        }
+        None => match authority.split_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (authority, None),
+        },
+    };
+    let port = match port {
+        Some(port) => port.parse().ok()?,
+        None => 80,
+    };
+    if host.is_empty() {
+        return None;
+    }
+    Some((host.to_string(), port, path.to_string()))
+}
+
+// The Host header of a request, IPv6 addresses go back in brackets
+fn host_header(host: &str, port: u16) -> String {
+    if host.contains(':') {
+        format!("[{}]:{}", host, port)
+    } else {
+        format!("{}:{}", host, port)
+    }
+}
+
+// GET the URL and require a 2xx status
+pub fn http(url: &str, timeout: Duration) -> Result<(), String> {
+    let (host, port, path) = parse_http_url(url).ok_or_else(|| format!("invalid URL {}", url))?;
+    let mut stream = connect(&host, port, timeout)?;
+    stream
+        .set_read_timeout(Some(timeout))
+        .and_then(|_| stream.set_write_timeout(Some(timeout)))
+        .map_err(|e| e.to_string())?;
+
+    // HTTP/1.0 keeps the answer unchunked, only the status line matters.
+    // The request goes out in one piece, servers may read it with a single call.
+    let request = format!(
+        "GET {} HTTP/1.0\r\nHost: {}\r\nUser-Agent: watchx\r\nConnection: close\r\n\r\n",
+        path,
+        host_header(&host, port)
+    );
+    stream
+        .write_all(request.as_bytes())
+        .map_err(|e| format!("{}: {}", url, e))?;
+
+    let mut status_line = String::new();
+    BufReader::new(stream.take(1024))
+        .read_line(&mut status_line)
+        .map_err(|e| match e.kind() {
+            ErrorKind::WouldBlock | ErrorKind::TimedOut => {
+                format!("{}: no answer within {}ms", url, timeout.as_millis())
+            }
+            _ => format!("{}: {}", url, e),
+        })?;
+    let status: u16 = status_line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|code| code.parse().ok())
+        .ok_or_else(|| format!("{}: not an HTTP response", url))?;
+    if (200..300).contains(&status) {
+        Ok(())
+    } else {
+        Err(format!("{} answered {}", url, status))
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum State {
+    #[default]
+    Pending,
+    Ready,
+    Failed(String),
+}
+
+// Readiness of a single run of a command, settled once by its probe
+#[derive(Debug, Clone, Default)]
+pub struct Readiness(Arc<Mutex<State>>);
+
+impl Readiness {
+    // Commands without a `ready` check are ready once started
+    pub fn ready() -> Self {
+        Readiness(Arc::new(Mutex::new(State::Ready)))
+    }
+
+    pub fn state(&self) -> State {
+        self.0.lock().unwrap().clone()
+    }
+
+    // Move out of Pending, returning false when the state was already settled
+    fn settle(&self, state: State) -> bool {
+        let mut current = self.0.lock().unwrap();
+        if *current != State::Pending {
+            return false;
+        }
+        *current = state;
+        true
+    }
+
+    // The process was stopped before it became ready
+    pub fn abandon(&self) {
+        self.settle(State::Failed("was stopped".to_string()));
+    }
+}
+
+// Flags the command once a line of its stdout matches the `ready.log` pattern
+#[derive(Debug, Clone)]
+pub struct LogMatcher {
+    pattern: Regex,
+    matched: Arc<AtomicBool>,
+}
+
+impl LogMatcher {
+    pub fn new(pattern: &str) -> Self {
+        LogMatcher {
+            // Validated when the config was loaded
+            pattern: Regex::new(pattern).expect("Invalid ready.log regex"),
+            matched: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    pub fn check(&self, line: &str) {
+        if !self.is_matched() && self.pattern.is_match(line) {
+            self.matched.store(true, Ordering::Relaxed);
+        }
+    }
+
+    fn is_matched(&self) -> bool {
+        self.matched.load(Ordering::Relaxed)
+    }
+}
+
+// Check the port and URL, whichever are set
+fn probe_endpoints(port: Option<u16>, url: Option<&str>, timeout: Duration) -> Result<(), String> {
+    if let Some(port) = port {
+        tcp(port, timeout)?;
+    }
+    if let Some(url) = url {
+        http(url, timeout)?;
+    }
+    Ok(())
+}
+
+// One round of every configured readiness probe
+fn probe(check: &ReadyCheck, matcher: Option<&LogMatcher>) -> Result<(), String> {
+    probe_endpoints(check.port, check.http.as_deref(), ATTEMPT_TIMEOUT)?;
+    if let Some(pattern) = &check.log {
+        if !matcher.is_some_and(LogMatcher::is_matched) {
+            return Err(format!("no output matched /{}/", pattern));
+        }
+    }
+    Ok(())
+}
+
+// Probe a freshly started command in the background. `matcher` is fed the
+// output of the command when the check matches log lines.
+// `since` is when the change that led to this start happened.
+pub fn watch_ready(
+    name: String,
+    check: ReadyCheck,
+    matcher: Option<LogMatcher>,
+    since: Instant,
+) -> Readiness {
+    let readiness = Readiness::default();
+
+    let state = readiness.clone();
+    thread::spawn(move || {
+        let started = Instant::now();
+        loop {
+            if state.state() != State::Pending {
+                return;
+            }
+            match probe(&check, matcher.as_ref()) {
+                Ok(()) => {
+                    if state.settle(State::Ready) {
+                        info!("{} is ready in {:.1}s", name, since.elapsed().as_secs_f64());
+                    }
+                    return;
+                }
+                Err(reason) if started.elapsed() >= check.timeout() => {
+                    if state.settle(State::Failed(format!("did not become ready: {}", reason))) {
+                        warn!(
+                            "{} not ready after {}ms: {}",
+                            name,
+                            check.timeout().as_millis(),
+                            reason
+                        );
+                    }
+                    return;
+                }
+                Err(_) => thread::sleep(PROBE_INTERVAL),
+            }
+        }
+    });
+
+    readiness
+}
+
+// Liveness of a single run of a command, probed in the background
+#[derive(Debug, Clone, Default)]
+pub struct Liveness {
+    failure: Arc<Mutex<Option<String>>>,
+    stopped: Arc<AtomicBool>,
+}
+
+impl Liveness {
+    // Why the command was found dead, reported only once
+    pub fn take_failure(&self) -> Option<String> {
+        self.failure.lock().unwrap().take()
+    }
+
+    // The process was stopped, probing ends
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::Relaxed);
+    }
+}
+
+// Probe a running command every `interval` once it is ready, and flag it after
+// `failures` consecutive failed checks. Failures only count once a check passed.
+pub fn watch_live(check: LivenessCheck, readiness: Readiness) -> Liveness {
+    let liveness = Liveness::default();
+    let state = liveness.clone();
+    thread::spawn(move || {
+        let mut failures = 0;
+        // A command that never answered is still starting up, however long that takes
+        let mut answered = false;
+        loop {
+            thread::sleep(check.interval());
+            if state.stopped.load(Ordering::Relaxed) {
+                return;
+            }
+            // Commands still starting up are not considered hung
+            if readiness.state() == State::Pending {
+                continue;
+            }
+            match probe_endpoints(check.port, check.http.as_deref(), check.timeout()) {
+                Ok(()) => {
+                    answered = true;
+                    failures = 0;
+                }
+                Err(_) if !answered => {}
+                Err(reason) => {
+                    failures += 1;
+                    if failures >= check.failures() {
+                        *state.failure.lock().unwrap() =
+                            Some(format!("{} liveness checks failed, last: {}", failures, reason));
+                        return;
+                    }
+                }
+            }
+        }
+    });
+    liveness
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_parse_http_url() {
+        assert_eq!(
+            parse_http_url("http://localhost:8080/health?full=1"),
+            Some(("localhost".to_string(), 8080, "/health?full=1".to_string()))
+        );
+        assert_eq!(
+            parse_http_url("http://127.0.0.1"),
+            Some(("127.0.0.1".to_string(), 80, "/".to_string()))
+        );
+        assert_eq!(
+            parse_http_url("http://[::1]:3000/"),
+            Some(("::1".to_string(), 3000, "/".to_string()))
+        );
+        assert_eq!(parse_http_url("https://localhost/"), None);
+    }
+
+    #[test]
+    fn test_host_header() {
+        assert_eq!(host_header("localhost", 8080), "localhost:8080");
+        assert_eq!(host_header("::1", 3000), "[::1]:3000");
+    }
+
+    // Answer a single request with the given status line
+    fn serve_once(status: &'static str) -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            // Closing with unread bytes would reset the connection before the answer is read
+            let mut request = Vec::new();
+            let mut buffer = [0u8; 1024];
+            while !request.ends_with(b"\r\n\r\n") {
+                match stream.read(&mut buffer) {
+                    Ok(0) | Err(_) => break,
+                    Ok(read) => request.extend_from_slice(&buffer[..read]),
+                }
+            }
+            let _ = write!(stream, "HTTP/1.1 {}\r\nContent-Length: 0\r\n\r\n", status);
+        });
+        port
+    }
+
+    #[test]
+    fn test_http_probe_requires_2xx() {
+        let port = serve_once("204 No Content");
+        assert!(http(&format!("http://127.0.0.1:{}/health", port), ATTEMPT_TIMEOUT).is_ok());
+
+        let port = serve_once("503 Service Unavailable");
+        let error = http(&format!("http://127.0.0.1:{}/health", port), ATTEMPT_TIMEOUT).unwrap_err();
+        assert!(error.ends_with("answered 503"));
+    }
+
+    #[test]
+    fn test_tcp_probe() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        assert!(tcp(port, ATTEMPT_TIMEOUT).is_ok());
+        drop(listener);
+        assert!(tcp(port, ATTEMPT_TIMEOUT).is_err());
+    }
+}
@@ -1,54 +1,239 @@
-use log::{info, warn};
-use std::collections::HashMap;
-use std::io::Error as IoError;
-use std::process::{Child, Command};
-use std::sync::atomic::{AtomicBool, Ordering};
-use scopeguard;
+use log::{error, info, warn};
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{Error as IoError, Write};
+use std::process::{Child, ChildStdin, ExitStatus};
+#[cfg(windows)]
+use std::process::Command;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-use crate::command;
+use crate::{command, logs, output, probes, signals};
+#[cfg(unix)]
+use crate::system;
+use crate::config::{
+    self, CommandConfig, Condition, Config, Dependency, Kind, PortPolicy, StopSignal,
+};
 
-static RESTART_IN_PROGRESS: AtomicBool = AtomicBool::new(false);
+// Whether a single process still exists
+#[cfg(windows)]
+fn is_alive(pid: u32) -> bool {
+    let pid = pid.to_string();
+    Command::new("tasklist")
+        .args(["/FI", &format!("PID eq {}", pid), "/NH"])
+        .output()
+        .map(|output| String::from_utf8_lossy(&output.stdout).contains(&pid))
+        .unwrap_or(false)
+}
 
-fn graceful_shutdown(pid: &str) -> Result<(), IoError> {
-    let os = std::env::consts::OS;
-    match os {
-        "linux" | "macos" => {
-            // First try SIGTERM
-            if let Ok(_) = Command::new("kill").arg("-15").arg(pid).output() {
-                // Give it some time to shutdown gracefully
-                thread::sleep(Duration::from_millis(500));
+#[cfg(unix)]
+fn is_alive(pid: u32) -> bool {
+    system::is_alive(pid)
+}
 
-                // Check if process still exists
-                if Command::new("ps").arg("-p").arg(pid).output().is_ok() {
-                    // If still running, force kill with SIGKILL
-                    Command::new("kill").arg("-9").arg(pid).output()?;
-                }
-            }
+// Poll until `check` reports the process gone or the timeout expires
+fn wait_until_gone(timeout: Duration, mut check: impl FnMut() -> bool) -> bool {
+    let start = Instant::now();
+    loop {
+        if check() {
+            return true;
         }
-        "windows" => {
-            // First try graceful shutdown
-            if let Ok(_) = Command::new("taskkill").args(&["/PID", pid]).output() {
-                thread::sleep(Duration::from_millis(500));
-
-                // Check if process still exists and force kill if necessary
-                if Command::new("tasklist")
-                    .args(&["/FI", &format!("PID eq {}", pid)])
-                    .output()
-                    .is_ok()
-                {
-                    Command::new("taskkill")
-                        .args(&["/F", "/PID", pid])
-                        .output()?;
+        if start.elapsed() >= timeout {
+            return false;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+}
+
+#[cfg(unix)]
+fn graceful_shutdown(pid: u32, signal: StopSignal, timeout: Duration) -> Result<(), IoError> {
+    system::send_signal(pid as i32, signal.number())?;
+
+    // Only escalate when the process is really still running
+    if !wait_until_gone(timeout, || !is_alive(pid)) {
+        warn!("Process {} ignored SIG{}, sending SIGKILL", pid, signal.name());
+        system::send_signal(pid as i32, libc::SIGKILL)?;
+    }
+    Ok(())
+}
+
+#[cfg(windows)]
+fn graceful_shutdown(pid: u32, _signal: StopSignal, timeout: Duration) -> Result<(), IoError> {
+    // First try graceful shutdown
+    let pid_str = pid.to_string();
+    Command::new("taskkill").args(["/PID", &pid_str]).output()?;
+
+    if !wait_until_gone(timeout, || !is_alive(pid)) {
+        Command::new("taskkill")
+            .args(["/F", "/PID", &pid_str])
+            .output()?;
+    }
+    Ok(())
+}
+
+// Signal the process group led by `pid`, reaching every process it spawned
+#[cfg(unix)]
+fn signal_group(pid: u32, signal: StopSignal, force: bool) -> Result<(), IoError> {
+    let number = if force { libc::SIGKILL } else { signal.number() };
+    system::send_signal(-(pid as i32), number)
+}
+
+#[cfg(windows)]
+fn signal_group(pid: u32, _signal: StopSignal, force: bool) -> Result<(), IoError> {
+    // taskkill walks the process tree with /T, there are no signals to choose from
+    let pid = pid.to_string();
+    let mut args = vec!["/T", "/PID", pid.as_str()];
+    if force {
+        args.insert(0, "/F");
+    }
+    Command::new("taskkill").args(args).output()?;
+    Ok(())
+}
+
+// Whether any process is still left in the group led by `pid`
+#[cfg(unix)]
+fn is_group_alive(pid: u32) -> bool {
+    system::is_group_alive(pid)
+}
+
+#[cfg(windows)]
+fn is_group_alive(_pid: u32) -> bool {
+    false
+}
+
+// How a stopped command ended
+pub struct StopReport {
+    pub name: String,
+    pub pid: u32,
+    pub status: Option<ExitStatus>,
+    pub killed: bool,
+}
+
+// Human readable exit status, e.g. "exited with code 1" or "terminated by SIGINT"
+pub fn describe_status(status: &ExitStatus) -> String {
+    if let Some(code) = status.code() {
+        return format!("exited with code {}", code);
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(signal) = status.signal() {
+            return format!("terminated by signal {}", signal_name(signal));
+        }
+    }
+    "exited".to_string()
+}
+
+#[cfg(unix)]
+fn signal_name(signal: i32) -> String {
+    match signal {
+        libc::SIGINT => "SIGINT".to_string(),
+        libc::SIGTERM => "SIGTERM".to_string(),
+        libc::SIGHUP => "SIGHUP".to_string(),
+        libc::SIGQUIT => "SIGQUIT".to_string(),
+        libc::SIGKILL => "SIGKILL".to_string(),
+        libc::SIGSEGV => "SIGSEGV".to_string(),
+        libc::SIGABRT => "SIGABRT".to_string(),
+        _ => signal.to_string(),
+    }
+}
+
+// Stop the given commands together with their whole process trees.
+// Every tree is signalled first so they shut down in parallel.
+fn stop(processes: &mut [Process], targets: &[usize]) -> Vec<StopReport> {
+    struct Stopping {
+        child: Child,
+        report: StopReport,
+        signal: StopSignal,
+        timeout: Duration,
+        done: bool,
+    }
+
+    let mut stopping = Vec::new();
+    for &index in targets {
+        let process = &mut processes[index];
+        let Some(child) = process.child.take() else {
+            continue;
+        };
+        process.terminal = None;
+        process.stdin = None;
+        process.readiness.abandon();
+        process.liveness.stop();
+        let pid = child.id();
+        let signal = process.config.stop_signal.unwrap_or_default();
+        if let (Some(log), None) = (&process.log, process.exited) {
+            log.note(&format!("stopping with SIG{}", signal.name()));
+        }
+        if let Err(e) = signal_group(pid, signal, false) {
+            warn!("Failed to stop process {}: {}", process.config.name, e);
+        }
+        stopping.push(Stopping {
+            child,
+            report: StopReport {
+                name: process.config.name.clone(),
+                pid,
+                status: None,
+                killed: false,
+            },
+            signal,
+            timeout: process.config.stop_timeout(),
+            done: false,
+        });
+    }
+
+    // Wait for each child and everything it spawned to exit, escalating once its timeout passes
+    let start = Instant::now();
+    while stopping.iter().any(|s| !s.done) {
+        for s in stopping.iter_mut().filter(|s| !s.done) {
+            if s.report.status.is_none() {
+                s.report.status = s.child.try_wait().ok().flatten();
+            }
+            if s.report.status.is_some() && !is_group_alive(s.report.pid) {
+                s.done = true;
+            } else if start.elapsed() >= s.timeout {
+                warn!(
+                    "Process {} did not exit within {}ms of SIG{}, killing process group",
+                    s.report.name,
+                    s.timeout.as_millis(),
+                    s.signal.name()
+                );
+                if let Err(e) = signal_group(s.report.pid, s.signal, true) {
+                    warn!("Failed to kill process {}: {}", s.report.name, e);
+                }
+                if s.report.status.is_none() {
+                    s.report.status = s.child.wait().ok();
                 }
+                s.report.killed = true;
+                s.done = true;
             }
         }
-        _ => {
-            warn!("Graceful shutdown not implemented for OS: {}", os);
+        if stopping.iter().any(|s| !s.done) {
+            thread::sleep(Duration::from_millis(100));
         }
     }
-    Ok(())
+
+    stopping.into_iter().map(|s| s.report).collect()
+}
+
+// Stop every running command before watchx exits and report how each one ended
+pub fn shutdown(processes: &mut [Process]) {
+    let all: Vec<usize> = (0..processes.len()).collect();
+    let start = Instant::now();
+    let reports = stop(processes, &all);
+
+    for report in &reports {
+        let outcome = match (&report.status, report.killed) {
+            (_, true) => "killed after stop timeout".to_string(),
+            (Some(status), false) => describe_status(status),
+            (None, false) => "stopped".to_string(),
+        };
+        info!("Stopped {} (PID: {}): {}", report.name, report.pid, outcome);
+    }
+    info!(
+        "Shutdown complete, {} process(es) stopped in {:.1}s",
+        reports.len(),
+        start.elapsed().as_secs_f64()
+    );
 }
 
 fn is_port_available(port: u16) -> bool {
@@ -56,52 +241,153 @@ fn is_port_available(port: u16) -> bool {
     std::net::TcpListener::bind(addr).is_ok()
 }
 
-fn force_kill(port: u16) {
-    let os = std::env::consts::OS;
-
-    // Function to get PIDs using a port
-    let get_pids = |port: u16| -> Vec<String> {
-        match os {
-            "linux" | "macos" => {
-                if let Ok(output) = Command::new("sh")
-                    .arg("-c")
-                    .arg(format!("lsof -t -i:{}", port))
-                    .output() {
-                    String::from_utf8_lossy(&output.stdout)
-                        .split_whitespace()
-                        .map(String::from)
-                        .collect()
-                } else {
-                    Vec::new()
-                }
-            }
-            "windows" => {
-                if let Ok(output) = Command::new("cmd")
-                    .args(&["/C", &format!("for /f \"tokens=5\" %a in ('netstat -ano ^| findstr :{} ^| findstr LISTENING') do @echo %a", port)])
-                    .output() {
-                    String::from_utf8_lossy(&output.stdout)
-                        .lines()
-                        .filter(|pid| pid.trim().parse::<u32>().is_ok())
-                        .map(String::from)
-                        .collect()
-                } else {
-                    Vec::new()
-                }
-            }
-            _ => Vec::new()
+// PIDs listening on a port
+#[cfg(unix)]
+fn get_pids(port: u16) -> Vec<u32> {
+    system::listening_pids(port)
+}
+
+#[cfg(windows)]
+fn get_pids(port: u16) -> Vec<u32> {
+    if let Ok(output) = Command::new("cmd")
+        .args(["/C", &format!("for /f \"tokens=5\" %a in ('netstat -ano ^| findstr :{} ^| findstr LISTENING') do @echo %a", port)])
+        .output() {
+        String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter_map(|pid| pid.trim().parse::<u32>().ok())
+            .collect()
+    } else {
+        Vec::new()
+    }
+}
+
+// Processes watchx spawned for the given commands, including everything they started
+#[cfg(unix)]
+fn owned_pids(processes: &[Process], targets: &[usize]) -> HashSet<u32> {
+    let roots: Vec<u32> = targets
+        .iter()
+        .filter_map(|&index| processes[index].child.as_ref().map(|child| child.id()))
+        .collect();
+    system::descendants(&roots)
+}
+
+#[cfg(windows)]
+fn owned_pids(processes: &[Process], targets: &[usize]) -> HashSet<u32> {
+    targets
+        .iter()
+        .filter_map(|&index| processes[index].child.as_ref().map(|child| child.id()))
+        .collect()
+}
+
+// Whether a process belongs to a tree watchx spawned, even if it left the process group
+#[cfg(unix)]
+fn is_owned(pid: u32, owned: &HashSet<u32>) -> bool {
+    if owned.contains(&pid) {
+        return true;
+    }
+    let mut current = pid;
+    for _ in 0..64 {
+        let Some(stat) = system::read_stat(current) else {
+            break;
+        };
+        if owned.contains(&stat.pgrp) || owned.contains(&stat.ppid) {
+            return true;
         }
-    };
+        if stat.ppid <= 1 {
+            break;
+        }
+        current = stat.ppid;
+    }
+    false
+}
+
+#[cfg(windows)]
+fn is_owned(pid: u32, owned: &HashSet<u32>) -> bool {
+    owned.contains(&pid)
+}
 
-    // Get initial list of PIDs
+// Describe a process for reporting, e.g. "1234 (docker-proxy -proto tcp)"
+fn describe(pid: u32) -> String {
+    #[cfg(unix)]
+    let command = system::cmdline(pid);
+    #[cfg(windows)]
+    let command = Command::new("tasklist")
+        .args(["/FI", &format!("PID eq {}", pid), "/FO", "CSV", "/NH"])
+        .output()
+        .ok()
+        .and_then(|output| {
+            let stdout = String::from_utf8_lossy(&output.stdout).to_string();
+            stdout.split(',').next().map(|name| name.trim_matches('"').to_string())
+        });
+
+    match command {
+        Some(command) => format!("{} ({})", pid, command),
+        None => pid.to_string(),
+    }
+}
+
+// The processes on a port that may be killed, sparing the ones watchx did not start unless the
+// policy says so. None when a foreign process holds the port and the policy is to fail, or
+// watchx is stopped while waiting for it.
+fn claim_port(port: u16, owned: &HashSet<u32>, policy: PortPolicy) -> Option<Vec<u32>> {
     let pids = get_pids(port);
     if pids.is_empty() {
         warn!("No processes found on port {}", port);
-        return;
+        return Some(pids);
     }
 
+    let (pids, foreign): (Vec<u32>, Vec<u32>) = pids
+        .into_iter()
+        .partition(|pid| policy == PortPolicy::Kill || is_owned(*pid, owned));
+
+    if !foreign.is_empty() {
+        for pid in &foreign {
+            warn!(
+                "Port {} is held by process {} which watchx did not start",
+                port,
+                describe(*pid)
+            );
+        }
+        match policy {
+            PortPolicy::Fail => {
+                error!(
+                    "Port {} is in use by another program, stop it or set port_policy: wait or kill",
+                    port
+                );
+                return None;
+            }
+            PortPolicy::Wait => {
+                info!("Waiting for port {} to be released...", port);
+                let mut last_notice = Instant::now();
+                while !is_port_available(port) {
+                    if signals::received().is_some() {
+                        return None;
+                    }
+                    if last_notice.elapsed() > Duration::from_secs(10) {
+                        info!("Still waiting for port {}", port);
+                        last_notice = Instant::now();
+                    }
+                    thread::sleep(Duration::from_millis(500));
+                }
+                return Some(Vec::new());
+            }
+            PortPolicy::Kill => {}
+        }
+    }
+    Some(pids)
+}
+
+// Kill the processes claimed on a port. What the command spawned is stopped with its own
+// stop signal and timeout.
+fn force_kill(port: u16, command: &CommandConfig, owned: &HashSet<u32>, pids: &[u32]) {
     // Try graceful shutdown first
-    for pid in &pids {
-        if let Err(e) = graceful_shutdown(pid) {
+    for pid in pids {
+        let (signal, timeout) = if is_owned(*pid, owned) {
+            (command.stop_signal.unwrap_or_default(), command.stop_timeout())
+        } else {
+            (StopSignal::default(), Duration::from_millis(config::DEFAULT_STOP_TIMEOUT_MS))
+        };
+        if let Err(e) = graceful_shutdown(*pid, signal, timeout) {
             warn!("Failed to gracefully shutdown process {}: {}", pid, e);
         } else {
             info!("Successfully terminated process {} on port {}", pid, port);
@@ -119,72 +405,671 @@ fn force_kill(port: u16) {
     }
 }
 
-pub fn restart(
-    children: &mut Vec<Child>,
-    commands: &[String],
-    env: &HashMap<String, String>,
-    port: u16,
-) {
-    if RESTART_IN_PROGRESS.load(Ordering::SeqCst) {
-        info!("Restart already in progress, skipping duplicate restart");
-        return;
+// A supervised command and its running child, if any
+pub struct Process {
+    pub config: CommandConfig,
+    pub child: Option<Child>,
+    /// Readiness of the current child, settled by its `ready` check
+    pub readiness: probes::Readiness,
+    /// Liveness of the current child, flagged by its `liveness` check
+    pub liveness: probes::Liveness,
+    /// When the current child was started
+    started: Option<Instant>,
+    /// How the current child ended, once it exited on its own
+    exited: Option<ExitStatus>,
+    /// Restarts after exits since the last file change
+    restarts: u32,
+    /// When the next restart after an exit or a failed liveness check is due
+    retry_at: Option<Instant>,
+    /// Why that restart happens, `exit` or `liveness`
+    retry_kind: &'static str,
+    /// Run the task again once the current run finishes
+    rerun: bool,
+    /// Why the command is started next, collected until it actually starts
+    pub trigger: command::Trigger,
+    /// How many times the command was started
+    starts: u32,
+    /// Where the output of the command is kept on disk
+    pub log: Option<logs::Log>,
+    /// watchx's end of the pseudo-terminal of the current child, for commands with `tty: true`
+    terminal: Option<File>,
+    /// Where typed input for the current child goes, unless it has a terminal
+    stdin: Option<ChildStdin>,
+    /// Signals when the output of the current child has been printed
+    drained: Option<output::Drained>,
+}
+
+impl Process {
+    pub fn new(config: CommandConfig) -> Self {
+        Process {
+            config,
+            child: None,
+            readiness: probes::Readiness::default(),
+            liveness: probes::Liveness::default(),
+            started: None,
+            exited: None,
+            restarts: 0,
+            retry_at: None,
+            retry_kind: "exit",
+            rerun: false,
+            trigger: command::Trigger::new("start"),
+            starts: 0,
+            log: None,
+            terminal: None,
+            stdin: None,
+            drained: None,
+        }
+    }
+
+    // A task that has not finished yet
+    pub fn is_running_task(&mut self) -> bool {
+        self.config.kind == Kind::Task
+            && self
+                .child
+                .as_mut()
+                .is_some_and(|child| matches!(child.try_wait(), Ok(None)))
+    }
+}
+
+// Rows and columns left for a command next to its prefix, falling back to 80x24 when
+// watchx does not run in a terminal
+#[cfg(unix)]
+fn terminal_size(prefix: &output::Prefix) -> (u16, u16) {
+    const MIN_COLUMNS: u16 = 20;
+
+    let (rows, columns) = system::window_size().unwrap_or((24, 80));
+    let columns = columns.saturating_sub(prefix.columns() as u16).max(MIN_COLUMNS);
+    (rows, columns)
+}
+
+// A pseudo-terminal for a command with `tty: true`, as watchx's end and the child's end
+#[cfg(unix)]
+fn open_terminal(command: &CommandConfig, prefix: &output::Prefix) -> (Option<File>, Option<File>) {
+    if !command.tty {
+        return (None, None);
+    }
+    let (rows, columns) = terminal_size(prefix);
+    match system::open_pty(rows, columns) {
+        Ok(pty) => (Some(pty.master), Some(pty.slave)),
+        Err(e) => {
+            warn!("Failed to open a terminal for {}, using pipes: {}", command.name, e);
+            (None, None)
+        }
+    }
+}
+
+#[cfg(windows)]
+fn open_terminal(command: &CommandConfig, _prefix: &output::Prefix) -> (Option<File>, Option<File>) {
+    if command.tty {
+        warn!("tty is not supported on Windows, running {} with pipes", command.name);
+    }
+    (None, None)
+}
+
+// Pass a new window size on to the commands running under a pseudo-terminal
+#[cfg(unix)]
+pub fn resize_terminals(processes: &[Process], config: &Config) {
+    for (index, process) in processes.iter().enumerate() {
+        let Some(terminal) = &process.terminal else {
+            continue;
+        };
+        let (rows, columns) = terminal_size(&output::Prefix::new(config, index));
+        if let Err(e) = system::set_window_size(terminal, rows, columns) {
+            warn!("Failed to resize the terminal of {}: {}", process.config.name, e);
+        }
     }
-    
-    // Set restart flag and ensure it's reset even if we panic
-    RESTART_IN_PROGRESS.store(true, Ordering::SeqCst);
-    let _reset_guard = scopeguard::guard((), |_| {
-        RESTART_IN_PROGRESS.store(false, Ordering::SeqCst);
-    });
-
-    // Force kill processes only once
-    force_kill(port);
-
-    // Kill existing child processes with timeout
-    for child in children.iter_mut() {
-        if let Err(e) = child.kill() {
-            warn!("Failed to kill process: {}", e);
-        }
-        
-        // Wait for process to exit with timeout
-        let start = std::time::Instant::now();
-        while child.try_wait().map(|s| s.is_none()).unwrap_or(false) {
-            if start.elapsed() > std::time::Duration::from_secs(5) {
-                warn!("Process kill timed out after 5 seconds");
-                break;
+}
+
+#[cfg(windows)]
+pub fn resize_terminals(_processes: &[Process], _config: &Config) {}
+
+// Free every port the command uses, returning false if one stays unavailable
+fn free_ports(command: &CommandConfig, config: &Config, owned: &HashSet<u32>) -> bool {
+    let policy = config.port_policy_for(command);
+
+    for &port in config.reclaimed_ports(command) {
+        // Ensure port is available with retries
+        let mut retries = 3;
+        while !is_port_available(port) && retries > 0 {
+            // Foreign holders are up to the port policy before anything is killed
+            let Some(pids) = claim_port(port, owned, policy) else {
+                return false;
+            };
+            if !pids.is_empty() {
+                warn!(
+                    "Port {} still in use, retrying kill... ({} attempts left)",
+                    port, retries
+                );
+                force_kill(port, command, owned, &pids);
             }
-            thread::sleep(Duration::from_millis(100));
+            thread::sleep(Duration::from_secs(1));
+            retries -= 1;
+        }
+
+        if !is_port_available(port) {
+            warn!("Could not free port {} after multiple attempts", port);
+            // Give it one last chance after a longer wait
+            thread::sleep(Duration::from_secs(3));
+            let Some(pids) = claim_port(port, owned, policy) else {
+                return false;
+            };
+            force_kill(port, command, owned, &pids);
         }
     }
-    children.clear();
+    true
+}
+
+// Called while a build runs, returns true when the build of the command at the index is stale
+pub type Cancel<'a> = dyn FnMut(usize) -> bool + 'a;
+
+// Run the build step of a command to completion, true when it succeeded or there is none
+fn build(processes: &[Process], index: usize, config: &Config, cancel: &mut Cancel) -> bool {
+    const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+    let process = &processes[index];
+    if process.config.build.is_none() {
+        return true;
+    }
+
+    let start = Instant::now();
+    let mut trigger = process.trigger.clone();
+    trigger.restart_count = process.starts;
+    if let Some(log) = &process.log {
+        log.separator("build", &trigger);
+    }
+    let prefix = output::Prefix::new(config, index);
+    let (terminal, tty) = open_terminal(&process.config, &prefix);
+    let Some(mut child) = command::execute_build(&process.config, config, &trigger, tty) else {
+        return false;
+    };
+    // Typed input is for the command itself, never for its build
+    drop(child.stdin.take());
+    let drained = output::attach(&mut child, terminal, prefix, None, process.log.clone());
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break status,
+            Ok(None) => {}
+            Err(e) => {
+                error!("Failed to wait for build {}: {}", process.config.name, e);
+                return false;
+            }
+        }
+
+        // Newer changes make this build stale, kill its whole tree and let the watcher rebuild
+        if cancel(index) {
+            if let Err(e) = signal_group(child.id(), StopSignal::default(), true) {
+                warn!("Failed to kill build {}: {}", process.config.name, e);
+            }
+            let _ = child.wait();
+            if let Some(log) = &process.log {
+                log.note("build cancelled");
+            }
+            warn!("Build {} cancelled, files changed while it was running", process.config.name);
+            return false;
+        }
+        thread::sleep(POLL_INTERVAL);
+    };
+    drained.wait();
 
-    // Ensure port is available with retries
-    let mut retries = 3;
-    while !is_port_available(port) && retries > 0 {
-        warn!(
-            "Port {} still in use, retrying kill... ({} attempts left)",
-            port, retries
+    if status.success() {
+        info!(
+            "Build {} succeeded in {:.1}s",
+            process.config.name,
+            start.elapsed().as_secs_f64()
         );
-        force_kill(port);
-        thread::sleep(Duration::from_secs(1));
-        retries -= 1;
+        return true;
     }
 
-    if !is_port_available(port) {
-        warn!("Could not free port {} after multiple attempts", port);
-        // Give it one last chance after a longer wait
-        thread::sleep(Duration::from_secs(3));
-        force_kill(port);
+    if let Some(log) = &process.log {
+        log.note(&format!("build failed, {}", describe_status(&status)));
+    }
+    if process.child.is_some() {
+        error!(
+            "Build {} failed ({}), keeping the previous process running",
+            process.config.name,
+            describe_status(&status)
+        );
+    } else {
+        error!(
+            "Build {} failed ({}), not starting it",
+            process.config.name,
+            describe_status(&status)
+        );
+    }
+    false
+}
+
+// Wait until a dependency meets its condition, or explain why it never will
+fn wait_for_dependency(
+    processes: &mut [Process],
+    dependent: &str,
+    dependency: &Dependency,
+    config: &Config,
+) -> Result<(), String> {
+    const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+    let Some(index) = config.index_of(&dependency.name) else {
+        return Err("is not defined".to_string());
+    };
+    let target = &mut processes[index];
+    let condition = dependency.condition_for(&target.config);
+    let Some(child) = target.child.as_mut() else {
+        return Err("is not running".to_string());
+    };
+
+    match condition {
+        Condition::Started => Ok(()),
+        Condition::Ready => {
+            let readiness = processes[index].readiness.clone();
+            let mut waiting = false;
+            loop {
+                match readiness.state() {
+                    probes::State::Ready => return Ok(()),
+                    probes::State::Failed(reason) => return Err(reason),
+                    probes::State::Pending => {}
+                }
+                if signals::received().is_some() {
+                    return Err("was interrupted".to_string());
+                }
+                if !waiting {
+                    info!("Waiting for {} to be ready before starting {}", dependency.name, dependent);
+                    waiting = true;
+                }
+                thread::sleep(POLL_INTERVAL);
+            }
+        }
+        Condition::Completed => {
+            let mut waiting = false;
+            loop {
+                match child.try_wait() {
+                    Ok(Some(status)) if status.success() => return Ok(()),
+                    Ok(Some(status)) => return Err(describe_status(&status)),
+                    Ok(None) => {}
+                    Err(e) => return Err(e.to_string()),
+                }
+                if signals::received().is_some() {
+                    return Err("was interrupted".to_string());
+                }
+                if !waiting {
+                    info!("Waiting for {} to complete before starting {}", dependency.name, dependent);
+                    waiting = true;
+                }
+                thread::sleep(POLL_INTERVAL);
+            }
+        }
+    }
+}
+
+// Start the commands at the given indices, in order, once their dependencies are up and their ports are free.
+// `since` is when the change behind this start happened, to report how long it took to get ready.
+fn start(
+    processes: &mut [Process],
+    targets: &[usize],
+    config: &Config,
+    owned: &HashSet<u32>,
+    since: Instant,
+) {
+    'targets: for &index in targets {
+        let name = processes[index].config.name.clone();
+        for dependency in processes[index].config.depends_on.clone() {
+            if let Err(reason) = wait_for_dependency(processes, &name, &dependency, config) {
+                warn!("Not starting {}, its dependency {} {}", name, dependency.name, reason);
+                continue 'targets;
+            }
+        }
+
+        // Tasks never listen on ports, so they never take part in port cleanup
+        let process = &mut processes[index];
+        if process.config.kind == Kind::Service && !free_ports(&process.config, config, owned) {
+            warn!("Not starting {}, its ports are unavailable", process.config.name);
+            continue;
+        }
+        let mut trigger = std::mem::take(&mut process.trigger);
+        trigger.restart_count = process.starts;
+        if let Some(log) = &process.log {
+            log.separator(&format!("start #{}", process.starts + 1), &trigger);
+        }
+        let prefix = output::Prefix::new(config, index);
+        let (terminal, tty) = open_terminal(&process.config, &prefix);
+        process.child = command::execute(&process.config, config, &trigger, tty);
+        let Some(child) = process.child.as_mut() else {
+            continue;
+        };
+        process.stdin = child.stdin.take();
+        // One handle is read for output, the other one stays for resizing
+        let reader = match terminal {
+            Some(terminal) => match terminal.try_clone() {
+                Ok(reader) => {
+                    process.terminal = Some(terminal);
+                    Some(reader)
+                }
+                Err(_) => Some(terminal),
+            },
+            None => None,
+        };
+        process.starts += 1;
+        process.started = Some(Instant::now());
+        process.exited = None;
+        let matcher = process
+            .config
+            .ready
+            .as_ref()
+            .and_then(|check| check.log.as_deref())
+            .map(probes::LogMatcher::new);
+        process.drained = Some(output::attach(child, reader, prefix, matcher.clone(), process.log.clone()));
+        process.readiness = match &process.config.ready {
+            Some(check) => probes::watch_ready(name, check.clone(), matcher, since),
+            None => probes::Readiness::ready(),
+        };
+        if let Some(check) = &process.config.liveness {
+            process.liveness = probes::watch_live(check.clone(), process.readiness.clone());
+        }
+    }
+}
+
+// Start every command for the first time
+pub fn start_all(processes: &mut [Process], config: &Config, cancel: &mut Cancel) {
+    let since = Instant::now();
+    let built: Vec<usize> = config
+        .start_order()
+        .into_iter()
+        .filter(|&index| build(processes, index, config, cancel))
+        .collect();
+    start(processes, &built, config, &HashSet::new(), since);
+}
+
+// Rebuild and restart the commands at the given indices along with everything depending on them.
+// A command whose build fails or is cancelled keeps its previous process running, and so do its dependents.
+pub fn restart(
+    processes: &mut [Process],
+    targets: &[usize],
+    config: &Config,
+    since: Instant,
+    cancel: &mut Cancel,
+) {
+    let mut skipped: Vec<usize> = Vec::new();
+    let mut built: Vec<usize> = Vec::new();
+    for index in config.with_dependents(targets) {
+        let blocked = processes[index]
+            .config
+            .depends_on
+            .iter()
+            .filter_map(|dependency| config.index_of(&dependency.name))
+            .any(|dependency| skipped.contains(&dependency));
+        if processes[index].trigger.kinds.is_empty() {
+            processes[index].trigger.add("dependency", &[]);
+        }
+        if blocked || !build(processes, index, config, cancel) {
+            skipped.push(index);
+            continue;
+        }
+        if !targets.contains(&index) {
+            info!(
+                "Restarting {} as it depends on a restarted command",
+                processes[index].config.name
+            );
+        }
+        built.push(index);
+    }
+    // A file change gives crashed commands a fresh set of restarts
+    for &index in &built {
+        processes[index].restarts = 0;
+        processes[index].retry_at = None;
+    }
+    respawn(processes, &built, config, since);
+}
+
+// Stop the commands at the given indices and start them again
+fn respawn(processes: &mut [Process], targets: &[usize], config: &Config, since: Instant) {
+    // A running task is never cut short, it runs again once it is done
+    let targets: Vec<usize> = targets
+        .iter()
+        .copied()
+        .filter(|&index| {
+            let process = &mut processes[index];
+            if !process.is_running_task() {
+                return true;
+            }
+            if !process.rerun {
+                info!("Task {} is still running, it will run again once it finishes", process.config.name);
+                process.rerun = true;
+            }
+            false
+        })
+        .collect();
+    if targets.is_empty() {
+        return;
+    }
+    for &index in &targets {
+        if processes[index].trigger.kinds.is_empty() {
+            processes[index].trigger.add("dependency", &[]);
+        }
     }
 
-    // Start new processes with enhanced output handling
+    // Remember what we spawned before the tree is torn down
+    let owned = owned_pids(processes, &targets);
+
+    // Stop the existing process trees
+    stop(processes, &targets);
+
     info!("Restarting...");
-    *children = command::execute(commands, env);
+    start(processes, &targets, config, &owned, since);
+}
+
+// Pass typed input on to a command, through its terminal or its stdin
+pub fn send_input(process: &mut Process, bytes: &[u8]) {
+    let result = match (&mut process.terminal, &mut process.stdin) {
+        (Some(terminal), _) => terminal.write_all(bytes),
+        (None, Some(stdin)) => stdin.write_all(bytes).and_then(|_| stdin.flush()),
+        (None, None) => {
+            warn!("{} is not running or no longer reads its input", process.config.name);
+            return;
+        }
+    };
+    if let Err(e) = result {
+        warn!("Failed to send input to {}: {}", process.config.name, e);
+        process.stdin = None;
+    }
+}
+
+// Close the stdin of a command, which reads as the end of its input
+pub fn close_input(process: &mut Process) {
+    if process.stdin.take().is_some() {
+        info!("Closed the input of {}", process.config.name);
+    }
+}
+
+// Short uptime, e.g. "42s", "3m 12s" or "1h 05m"
+fn format_uptime(elapsed: Duration) -> String {
+    let seconds = elapsed.as_secs();
+    match seconds {
+        0..=59 => format!("{}s", seconds),
+        60..=3599 => format!("{}m {:02}s", seconds / 60, seconds % 60),
+        _ => format!("{}h {:02}m", seconds / 3600, seconds % 3600 / 60),
+    }
+}
+
+// Report every command with its PID and how long it has been running
+pub fn list(processes: &mut [Process]) {
+    let width = processes
+        .iter()
+        .map(|process| process.config.name.chars().count())
+        .max()
+        .unwrap_or(0);
+    info!("Processes:");
+    for (index, process) in processes.iter_mut().enumerate() {
+        let state = match process.child.as_mut() {
+            Some(child) => match child.try_wait() {
+                Ok(None) => format!(
+                    "PID {}, up {}",
+                    child.id(),
+                    format_uptime(process.started.map(|started| started.elapsed()).unwrap_or_default())
+                ),
+                Ok(Some(status)) => describe_status(&status),
+                Err(e) => e.to_string(),
+            },
+            None => "not running".to_string(),
+        };
+        info!("  {} {:<width$}  {}", index + 1, process.config.name, state, width = width);
+    }
+}
+
+// Delay before the given restart after an exit, doubling each time
+fn backoff(restarts: u32) -> Duration {
+    const BASE: Duration = Duration::from_millis(500);
+    const MAX: Duration = Duration::from_secs(30);
+
+    BASE.saturating_mul(2u32.saturating_pow(restarts)).min(MAX)
+}
+
+// Schedule the next restart of a command, backing off with every restart in a row
+fn retry(process: &mut Process, kind: &'static str, now: Instant) {
+    let delay = backoff(process.restarts);
+    info!(
+        "Restarting {} in {:.1}s ({} of {})",
+        process.config.name,
+        delay.as_secs_f64(),
+        process.restarts + 1,
+        process.config.max_restarts()
+    );
+    process.retry_at = Some(now + delay);
+    process.retry_kind = kind;
+}
+
+// Report commands that exited on their own and restart them as their `restart` policy says,
+// along with hung commands whose restart is due.
+// Finished tasks report whether they passed and run again if a restart was queued meanwhile.
+// A command that ran for a while before exiting is not crash looping, so its count starts over.
+pub fn supervise(processes: &mut [Process], config: &Config) {
+    const STABLE_AFTER: Duration = Duration::from_secs(30);
+
+    let now = Instant::now();
+    let mut due = Vec::new();
+    for (index, process) in processes.iter_mut().enumerate() {
+        // A hung command is still running when its restart is due
+        if process.retry_at.is_some_and(|retry_at| retry_at <= now) {
+            process.retry_at = None;
+            process.restarts += 1;
+            process.trigger.add(process.retry_kind, &[]);
+            due.push(index);
+            continue;
+        }
+
+        if process.exited.is_none() {
+            let status = match process.child.as_mut().map(|child| child.try_wait()) {
+                Some(Ok(Some(status))) => status,
+                _ => continue,
+            };
+            process.exited = Some(status);
+            process.liveness.stop();
+            process.readiness.abandon();
+            // Its last lines come before what watchx says about the exit
+            if let Some(drained) = process.drained.take() {
+                drained.wait();
+            }
+            if let Some(log) = &process.log {
+                log.note(&describe_status(&status));
+            }
+
+            let name = &process.config.name;
+            if process.config.kind == Kind::Task {
+                let elapsed = process.started.map(|started| started.elapsed()).unwrap_or_default();
+                if status.success() {
+                    info!("Task {} passed in {:.1}s", name, elapsed.as_secs_f64());
+                } else {
+                    error!(
+                        "Task {} failed in {:.1}s ({})",
+                        name,
+                        elapsed.as_secs_f64(),
+                        describe_status(&status)
+                    );
+                }
+                if process.rerun {
+                    process.rerun = false;
+                    process.trigger.add("dependency", &[]);
+                    due.push(index);
+                }
+                continue;
+            }
+            if status.success() {
+                info!("Process {} {}", name, describe_status(&status));
+            } else {
+                warn!("Process {} {}", name, describe_status(&status));
+            }
+
+            if !process.config.restart.applies_to(status.success()) {
+                continue;
+            }
+            if process.started.is_some_and(|started| started.elapsed() >= STABLE_AFTER) {
+                process.restarts = 0;
+            }
+            if process.restarts >= process.config.max_restarts() {
+                error!(
+                    "Process {} exited {} times in a row, waiting for the next file change",
+                    name,
+                    process.restarts + 1
+                );
+                continue;
+            }
+            retry(process, "exit", now);
+        }
+    }
+    if !due.is_empty() {
+        respawn(processes, &config.with_dependents(&due), config, now);
+    }
+}
+
+// Schedule a restart of the commands whose liveness checks failed, which `supervise` carries out
+// along with their dependents. Like restarts after an exit, these back off and count towards
+// `max_restarts`. Nothing changed on disk, so there is nothing to rebuild.
+pub fn restart_unhealthy(processes: &mut [Process]) {
+    let now = Instant::now();
+    for process in processes.iter_mut() {
+        let Some(reason) = process.liveness.take_failure() else {
+            continue;
+        };
+        let running = process
+            .child
+            .as_mut()
+            .is_some_and(|child| matches!(child.try_wait(), Ok(None)));
+        if !running {
+            continue;
+        }
+        warn!("{} looks hung: {}", process.config.name, reason);
+        if let Some(log) = &process.log {
+            log.note(&format!("looks hung: {}", reason));
+        }
+        if process.restarts >= process.config.max_restarts() {
+            error!(
+                "Process {} still looks hung after {} restarts in a row, waiting for the next file change",
+                process.config.name, process.restarts
+            );
+            continue;
+        }
+        retry(process, "liveness", now);
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_backoff_doubles_up_to_a_limit() {
+        assert_eq!(backoff(0), Duration::from_millis(500));
+        assert_eq!(backoff(1), Duration::from_millis(1000));
+        assert_eq!(backoff(3), Duration::from_millis(4000));
+        assert_eq!(backoff(10), Duration::from_secs(30));
+        assert_eq!(backoff(40), Duration::from_secs(30));
+    }
+
+    #[test]
+    fn test_format_uptime() {
+        assert_eq!(format_uptime(Duration::from_secs(42)), "42s");
+        assert_eq!(format_uptime(Duration::from_secs(192)), "3m 12s");
+        assert_eq!(format_uptime(Duration::from_secs(3900)), "1h 05m");
+    }
+
     #[test]
     fn test_port_available() {
         assert!(is_port_available(0)); // Port 0 tells OS to assign random port
@@ -0,0 +1,65 @@
+use std::io::Error as IoError;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, OnceLock};
+
+// Number of the last termination signal received, 0 while none arrived
+static RECEIVED: OnceLock<Arc<AtomicUsize>> = OnceLock::new();
+
+// Set when the terminal watchx runs in was resized
+static RESIZED: OnceLock<Arc<AtomicBool>> = OnceLock::new();
+
+#[cfg(unix)]
+const SIGNALS: &[i32] = &[
+    signal_hook::consts::SIGINT,
+    signal_hook::consts::SIGTERM,
+    signal_hook::consts::SIGHUP,
+];
+
+#[cfg(windows)]
+const SIGNALS: &[i32] = &[signal_hook::consts::SIGINT, signal_hook::consts::SIGTERM];
+
+// Trap SIGINT, SIGTERM and SIGHUP so watchx can stop its children before exiting
+pub fn install() -> Result<(), IoError> {
+    let received = RECEIVED.get_or_init(|| Arc::new(AtomicUsize::new(0)));
+    for &signal in SIGNALS {
+        signal_hook::flag::register_usize(signal, Arc::clone(received), signal as usize)?;
+    }
+    #[cfg(unix)]
+    {
+        let resized = RESIZED.get_or_init(|| Arc::new(AtomicBool::new(false)));
+        signal_hook::flag::register(signal_hook::consts::SIGWINCH, Arc::clone(resized))?;
+    }
+    Ok(())
+}
+
+// Whether the terminal was resized since the last call
+pub fn take_resized() -> bool {
+    RESIZED
+        .get()
+        .is_some_and(|resized| resized.swap(false, Ordering::SeqCst))
+}
+
+// The termination signal watchx received, if any
+pub fn received() -> Option<i32> {
+    let signal = RECEIVED.get()?.load(Ordering::SeqCst);
+    if signal == 0 {
+        None
+    } else {
+        Some(signal as i32)
+    }
+}
+
+pub fn name(signal: i32) -> &'static str {
+    match signal {
+        signal_hook::consts::SIGINT => "SIGINT",
+        signal_hook::consts::SIGTERM => "SIGTERM",
+        #[cfg(unix)]
+        signal_hook::consts::SIGHUP => "SIGHUP",
+        _ => "signal",
+    }
+}
+
+// Conventional shell exit status for a process ended by `signal`
+pub fn exit_code(signal: i32) -> i32 {
+    128 + signal
+}
@@ -0,0 +1,392 @@
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::Error as IoError;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::ptr;
+
+// Send a signal to a single process, or to a whole group when `pid` is negative
+pub fn send_signal(pid: i32, signal: i32) -> Result<(), IoError> {
+    if unsafe { libc::kill(pid, signal) } == 0 {
+        return Ok(());
+    }
+    let error = IoError::last_os_error();
+    if error.raw_os_error() == Some(libc::ESRCH) {
+        // Already gone, nothing left to signal
+        return Ok(());
+    }
+    Err(error)
+}
+
+// Whether a process exists and has not yet exited
+pub fn is_alive(pid: u32) -> bool {
+    let exists = unsafe { libc::kill(pid as i32, 0) } == 0
+        || IoError::last_os_error().raw_os_error() == Some(libc::EPERM);
+    if !exists {
+        return false;
+    }
+
+    // Zombies still answer to signal 0 until their parent reaps them
+    match read_stat(pid) {
+        Some(stat) => stat.state != 'Z',
+        None => exists,
+    }
+}
+
+// Whether any live process is left in the process group
+#[cfg(target_os = "linux")]
+pub fn is_group_alive(pgid: u32) -> bool {
+    process_table()
+        .iter()
+        .any(|(_, stat)| stat.pgrp == pgid && stat.state != 'Z')
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn is_group_alive(pgid: u32) -> bool {
+    unsafe { libc::kill(-(pgid as i32), 0) == 0 }
+}
+
+// Fields of interest from /proc/<pid>/stat
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ProcStat {
+    pub state: char,
+    pub ppid: u32,
+    pub pgrp: u32,
+}
+
+// Parse /proc/<pid>/stat, where the command name may itself contain spaces and parentheses
+pub fn parse_stat(content: &str) -> Option<ProcStat> {
+    let rest = &content[content.rfind(')')? + 1..];
+    let mut fields = rest.split_whitespace();
+    let state = fields.next()?.chars().next()?;
+    let ppid = fields.next()?.parse().ok()?;
+    let pgrp = fields.next()?.parse().ok()?;
+    Some(ProcStat { state, ppid, pgrp })
+}
+
+pub fn read_stat(pid: u32) -> Option<ProcStat> {
+    let content = fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    parse_stat(&content)
+}
+
+// Every process with its parent and group
+#[cfg(target_os = "linux")]
+pub fn process_table() -> Vec<(u32, ProcStat)> {
+    all_pids()
+        .into_iter()
+        .filter_map(|pid| read_stat(pid).map(|stat| (pid, stat)))
+        .collect()
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn process_table() -> Vec<(u32, ProcStat)> {
+    let Ok(output) = std::process::Command::new("ps")
+        .args(["-A", "-o", "pid=,ppid=,pgid=,stat="])
+        .output()
+    else {
+        return Vec::new();
+    };
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let pid = fields.next()?.parse().ok()?;
+            let ppid = fields.next()?.parse().ok()?;
+            let pgrp = fields.next()?.parse().ok()?;
+            let state = fields.next()?.chars().next()?;
+            Some((pid, ProcStat { state, ppid, pgrp }))
+        })
+        .collect()
+}
+
+// The given processes together with everything they spawned
+pub fn descendants(roots: &[u32]) -> HashSet<u32> {
+    let table = process_table();
+    let mut found: HashSet<u32> = roots.iter().copied().collect();
+    loop {
+        let before = found.len();
+        for (pid, stat) in &table {
+            if found.contains(&stat.ppid) {
+                found.insert(*pid);
+            }
+        }
+        if found.len() == before {
+            return found;
+        }
+    }
+}
+
+// Full command line of a process, for reporting
+#[cfg(target_os = "linux")]
+pub fn cmdline(pid: u32) -> Option<String> {
+    let raw = fs::read(format!("/proc/{}/cmdline", pid)).ok()?;
+    let parts: Vec<String> = raw
+        .split(|byte| *byte == 0)
+        .filter(|part| !part.is_empty())
+        .map(|part| String::from_utf8_lossy(part).into_owned())
+        .collect();
+    if parts.is_empty() {
+        None
+    } else {
+        Some(parts.join(" "))
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn cmdline(pid: u32) -> Option<String> {
+    let output = std::process::Command::new("ps")
+        .args(["-o", "command=", "-p", &pid.to_string()])
+        .output()
+        .ok()?;
+    let command = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if command.is_empty() {
+        None
+    } else {
+        Some(command)
+    }
+}
+
+// Every numeric entry in /proc
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn all_pids() -> Vec<u32> {
+    let Ok(entries) = fs::read_dir("/proc") else {
+        return Vec::new();
+    };
+    entries
+        .flatten()
+        .filter_map(|entry| entry.file_name().to_str()?.parse().ok())
+        .collect()
+}
+
+// Socket inodes listening on `port` in a /proc/net/tcp or tcp6 table
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+pub fn parse_listening_inodes(table: &str, port: u16) -> Vec<u64> {
+    const TCP_LISTEN: &str = "0A";
+
+    table
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let local = fields.get(1)?;
+            let state = fields.get(3)?;
+            let inode = fields.get(9)?;
+
+            let local_port = u16::from_str_radix(local.rsplit(':').next()?, 16).ok()?;
+            if local_port != port || *state != TCP_LISTEN {
+                return None;
+            }
+            inode.parse().ok()
+        })
+        .filter(|inode| *inode != 0)
+        .collect()
+}
+
+// PIDs holding a listening socket on `port`, found through /proc
+#[cfg(target_os = "linux")]
+pub fn listening_pids(port: u16) -> Vec<u32> {
+    let inodes: Vec<u64> = ["/proc/net/tcp", "/proc/net/tcp6"]
+        .iter()
+        .filter_map(|path| fs::read_to_string(path).ok())
+        .flat_map(|table| parse_listening_inodes(&table, port))
+        .collect();
+    if inodes.is_empty() {
+        return Vec::new();
+    }
+
+    let targets: Vec<String> = inodes
+        .iter()
+        .map(|inode| format!("socket:[{}]", inode))
+        .collect();
+
+    all_pids()
+        .into_iter()
+        .filter(|pid| {
+            let Ok(fds) = fs::read_dir(format!("/proc/{}/fd", pid)) else {
+                // Processes of other users cannot be inspected
+                return false;
+            };
+            fds.flatten().any(|fd| {
+                fs::read_link(fd.path())
+                    .map(|link| targets.iter().any(|target| link.as_os_str() == target.as_str()))
+                    .unwrap_or(false)
+            })
+        })
+        .collect()
+}
+
+// Without /proc, fall back to lsof
+#[cfg(not(target_os = "linux"))]
+pub fn listening_pids(port: u16) -> Vec<u32> {
+    std::process::Command::new("lsof")
+        .args(["-t", &format!("-iTCP:{}", port), "-sTCP:LISTEN"])
+        .output()
+        .map(|output| {
+            String::from_utf8_lossy(&output.stdout)
+                .split_whitespace()
+                .filter_map(|pid| pid.parse().ok())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+// A pseudo-terminal pair, the child gets `slave` as its terminal and watchx reads `master`
+pub struct Pty {
+    pub master: File,
+    pub slave: File,
+}
+
+fn window(rows: u16, cols: u16) -> libc::winsize {
+    libc::winsize {
+        ws_row: rows,
+        ws_col: cols,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    }
+}
+
+// Keep a descriptor from leaking into every other child watchx spawns
+fn set_cloexec(fd: RawFd) -> Result<(), IoError> {
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFD) };
+    if flags < 0 || unsafe { libc::fcntl(fd, libc::F_SETFD, flags | libc::FD_CLOEXEC) } < 0 {
+        return Err(IoError::last_os_error());
+    }
+    Ok(())
+}
+
+pub fn open_pty(rows: u16, cols: u16) -> Result<Pty, IoError> {
+    let mut master: libc::c_int = -1;
+    let mut slave: libc::c_int = -1;
+    // Linux takes the window size as *const and macOS as *mut
+    let mut size = window(rows, cols);
+    let size = &mut size as *mut libc::winsize as _;
+    if unsafe { libc::openpty(&mut master, &mut slave, ptr::null_mut(), ptr::null_mut(), size) } != 0 {
+        return Err(IoError::last_os_error());
+    }
+    let pty = unsafe {
+        Pty {
+            master: File::from_raw_fd(master),
+            slave: File::from_raw_fd(slave),
+        }
+    };
+    set_cloexec(master)?;
+    set_cloexec(slave)?;
+    Ok(pty)
+}
+
+// Rows and columns of the terminal watchx writes to, if it writes to one
+pub fn window_size() -> Option<(u16, u16)> {
+    let mut size = window(0, 0);
+    if unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) } != 0 || size.ws_col == 0 {
+        return None;
+    }
+    Some((size.ws_row, size.ws_col))
+}
+
+// Resize a pseudo-terminal, the kernel tells the programs on it with SIGWINCH
+pub fn set_window_size(terminal: &File, rows: u16, cols: u16) -> Result<(), IoError> {
+    let size = window(rows, cols);
+    if unsafe { libc::ioctl(terminal.as_raw_fd(), libc::TIOCSWINSZ, &size) } != 0 {
+        return Err(IoError::last_os_error());
+    }
+    Ok(())
+}
+
+// Run in the child before exec: lead a new session, and so a new process group,
+// with the terminal on stdin as its controlling terminal
+pub fn take_controlling_terminal() -> Result<(), IoError> {
+    if unsafe { libc::setsid() } < 0 {
+        return Err(IoError::last_os_error());
+    }
+    if unsafe { libc::ioctl(libc::STDIN_FILENO, libc::TIOCSCTTY, 0) } < 0 {
+        return Err(IoError::last_os_error());
+    }
+    Ok(())
+}
+
+// Whether watchx is the foreground job of the terminal on stdin, background jobs are
+// stopped when they read from it or change its settings
+pub fn is_foreground() -> bool {
+    let foreground = unsafe { libc::tcgetpgrp(libc::STDIN_FILENO) };
+    foreground >= 0 && foreground == unsafe { libc::getpgrp() }
+}
+
+// Block until stdin has something to read, without reading it
+pub fn wait_for_input() -> Result<(), IoError> {
+    let mut stdin = libc::pollfd {
+        fd: libc::STDIN_FILENO,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    if unsafe { libc::poll(&mut stdin, 1, -1) } < 0 {
+        return Err(IoError::last_os_error());
+    }
+    Ok(())
+}
+
+// Settings of the terminal on stdin
+pub fn terminal_settings() -> Result<libc::termios, IoError> {
+    let mut settings: libc::termios = unsafe { std::mem::zeroed() };
+    if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut settings) } != 0 {
+        return Err(IoError::last_os_error());
+    }
+    Ok(settings)
+}
+
+pub fn set_terminal_settings(settings: &libc::termios) -> Result<(), IoError> {
+    if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, settings) } != 0 {
+        return Err(IoError::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_stat_with_tricky_command_name() {
+        let stat = parse_stat("4242 (my (weird) app) S 1 4240 4240 0 -1 4194560").unwrap();
+        assert_eq!(
+            stat,
+            ProcStat {
+                state: 'S',
+                ppid: 1,
+                pgrp: 4240
+            }
+        );
+        assert!(parse_stat("garbage").is_none());
+    }
+
+    #[test]
+    fn test_parse_listening_inodes() {
+        let table = "  sl  local_address rem_address   st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode
+   0: 00000000:1F90 00000000:0000 0A 00000000:00000000 00:00000000 00000000  1000        0 51234 1 0000000000000000 100 0 0 10 0
+   1: 0100007F:0BB8 00000000:0000 0A 00000000:00000000 00:00000000 00000000  1000        0 51235 1 0000000000000000 100 0 0 10 0
+   2: 0100007F:1F90 0100007F:D2F0 01 00000000:00000000 00:00000000 00000000  1000        0 51236 1 0000000000000000 20 4 30 10 -1
+";
+        assert_eq!(parse_listening_inodes(table, 8080), vec![51234]);
+        assert_eq!(parse_listening_inodes(table, 3000), vec![51235]);
+        assert!(parse_listening_inodes(table, 9229).is_empty());
+
+        let table6 = "  sl  local_address                         remote_address                        st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode
+   0: 00000000000000000000000000000000:2406 00000000000000000000000000000000:0000 0A 00000000:00000000 00:00000000 00000000  1000        0 61000 1 0000000000000000 100 0 0 10 0
+";
+        assert_eq!(parse_listening_inodes(table6, 9222), vec![61000]);
+    }
+
+    #[test]
+    fn test_is_alive_for_own_process() {
+        assert!(is_alive(std::process::id()));
+    }
+
+    #[test]
+    fn test_descendants_include_spawned_children() {
+        let mut child = std::process::Command::new("sleep").arg("5").spawn().unwrap();
+        let tree = descendants(&[std::process::id()]);
+        let _ = child.kill();
+        let _ = child.wait();
+
+        assert!(tree.contains(&std::process::id()));
+        assert!(tree.contains(&child.id()));
+    }
+}
@@ -1,20 +1,16 @@
 use glob::Pattern;
 use log::{error, info, warn};
 use notify::{RecommendedWatcher, RecursiveMode, Result, Watcher};
+use std::cell::Cell;
 use std::env;
-use std::path::Path;
-use std::sync::{mpsc::channel, Arc, Mutex};
-use std::thread;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::channel;
 use std::time::{Duration, Instant};
 
-use crate::{command, config, processes};
+use crate::{command, config, keys, logs, output, processes, signals};
 
 pub fn should_ignore(path: &Path, ignore_patterns: &Option<Vec<String>>) -> bool {
     if let Some(patterns) = ignore_patterns {
-        // Convert path to string for pattern matching
-        let path_str = path.to_str().unwrap_or("");
-        let is_dir = path.is_dir();
-
         // First check if the file ends with a tilde (~) - common backup files
         if let Some(file_name) = path.file_name() {
             if let Some(file_str) = file_name.to_str() {
@@ -24,36 +20,83 @@ pub fn should_ignore(path: &Path, ignore_patterns: &Option<Vec<String>>) -> bool
             }
         }
 
-        // Check if the path itself matches any pattern
+        return matches_patterns(path, patterns);
+    }
+
+    false
+}
+
+// Whether the path or one of its parent directories matches a glob or /regex/ pattern
+pub fn matches_patterns(path: &Path, patterns: &[String]) -> bool {
+    // Convert path to string for pattern matching
+    let path_str = path.to_str().unwrap_or("");
+    let is_dir = path.is_dir();
+
+    // Check if the path itself matches any pattern
+    for pattern in patterns {
+        // Check if pattern is a regex (enclosed in /)
+        if pattern.starts_with('/') && pattern.ends_with('/') && pattern.len() > 2 {
+            // Extract the regex pattern without the slashes
+            let regex_pattern = &pattern[1..pattern.len() - 1];
+
+            // Try to compile the regex
+            if let Ok(regex) = regex::Regex::new(regex_pattern) {
+                // Check if the path matches the regex
+                if regex.is_match(path_str) {
+                    return true;
+                }
+
+                // For directories with trailing slash convention
+                if is_dir && regex.is_match(&format!("{}/", path_str)) {
+                    return true;
+                }
+            }
+        } else {
+            // Handle as a glob pattern
+            if let Ok(glob_pattern) = Pattern::new(pattern) {
+                if glob_pattern.matches(path_str) {
+                    return true;
+                }
+
+                // For directories, check with trailing slash if pattern ends with slash
+                if is_dir
+                    && pattern.ends_with('/')
+                    && glob_pattern.matches(&format!("{}/", path_str))
+                {
+                    return true;
+                }
+            }
+        }
+    }
+
+    // Check all parent directories recursively
+    let mut current = path;
+    while let Some(parent) = current.parent() {
+        if parent.as_os_str().is_empty() {
+            break; // Reached root
+        }
+
+        let parent_str = parent.to_str().unwrap_or("");
+
         for pattern in patterns {
-            // Check if pattern is a regex (enclosed in /)
             if pattern.starts_with('/') && pattern.ends_with('/') && pattern.len() > 2 {
-                // Extract the regex pattern without the slashes
+                // Regex pattern
                 let regex_pattern = &pattern[1..pattern.len() - 1];
-
-                // Try to compile the regex
                 if let Ok(regex) = regex::Regex::new(regex_pattern) {
-                    // Check if the path matches the regex
-                    if regex.is_match(path_str) {
-                        return true;
-                    }
-
-                    // For directories with trailing slash convention
-                    if is_dir && regex.is_match(&format!("{}/", path_str)) {
+                    if regex.is_match(parent_str) {
                         return true;
                     }
                 }
             } else {
-                // Handle as a glob pattern
+                // Glob pattern
                 if let Ok(glob_pattern) = Pattern::new(pattern) {
-                    if glob_pattern.matches(path_str) {
+                    if glob_pattern.matches(parent_str) {
                         return true;
                     }
 
-                    // For directories, check with trailing slash if pattern ends with slash
-                    if is_dir
-                        && pattern.ends_with('/')
-                        && glob_pattern.matches(&format!("{}/", path_str))
+                    // Parents are always directories, so honour the trailing slash convention
+                    if pattern.ends_with('/')
+                        && glob_pattern.matches(&format!("{}/", parent_str))
                     {
                         return true;
                     }
@@ -61,36 +104,7 @@ pub fn should_ignore(path: &Path, ignore_patterns: &Option<Vec<String>>) -> bool
             }
         }
 
-        // Check all parent directories recursively
-        let mut current = path;
-        while let Some(parent) = current.parent() {
-            if parent.as_os_str().is_empty() {
-                break; // Reached root
-            }
-
-            let parent_str = parent.to_str().unwrap_or("");
-
-            for pattern in patterns {
-                if pattern.starts_with('/') && pattern.ends_with('/') && pattern.len() > 2 {
-                    // Regex pattern
-                    let regex_pattern = &pattern[1..pattern.len() - 1];
-                    if let Ok(regex) = regex::Regex::new(regex_pattern) {
-                        if regex.is_match(parent_str) {
-                            return true;
-                        }
-                    }
-                } else {
-                    // Glob pattern
-                    if let Ok(glob_pattern) = Pattern::new(pattern) {
-                        if glob_pattern.matches(parent_str) {
-                            return true;
-                        }
-                    }
-                }
-            }
-
-            current = parent;
-        }
+        current = parent;
     }
 
     false
@@ -196,14 +210,90 @@ fn get_file_icon(path: &Path) -> &'static str {
     }
 }
 
-fn make_clickable(path: &Path) -> String {
+// A configured watch root with its resolved location
+pub struct WatchRoot {
+    pub dir: config::WatchDir,
+    canonical: PathBuf,
+}
+
+impl WatchRoot {
+    pub fn new(dir: config::WatchDir) -> Self {
+        let canonical = Path::new(&dir.path)
+            .canonicalize()
+            .unwrap_or_else(|_| PathBuf::from(&dir.path));
+        WatchRoot { dir, canonical }
+    }
+
+    // Path relative to this root, if the path lives under it
+    pub fn relative<'a>(&self, path: &'a Path) -> Option<&'a Path> {
+        path.strip_prefix(&self.dir.path)
+            .or_else(|_| path.strip_prefix(&self.canonical))
+            .ok()
+    }
+
+    // Name shown for this root in the change output
+    pub fn label(&self) -> String {
+        let trimmed = self.dir.path.trim_end_matches('/');
+        if trimmed.is_empty() || trimmed == "." {
+            // The current directory is shown by its own name
+            env::current_dir()
+                .ok()
+                .and_then(|dir| dir.file_name().map(|name| name.to_string_lossy().into_owned()))
+                .unwrap_or_else(|| ".".to_string())
+        } else {
+            trimmed.to_string()
+        }
+    }
+}
+
+// Find the most specific root that contains the path
+pub fn find_root<'a>(roots: &'a [WatchRoot], path: &Path) -> Option<&'a WatchRoot> {
+    roots
+        .iter()
+        .filter_map(|root| root.relative(path).map(|rel| (root, rel)))
+        .min_by_key(|(_, rel)| rel.components().count())
+        .map(|(root, _)| root)
+}
+
+pub fn should_ignore_in_root(
+    path: &Path,
+    root: &WatchRoot,
+    ignore_patterns: &Option<Vec<String>>,
+) -> bool {
+    if should_ignore(path, ignore_patterns) || should_ignore(path, &root.dir.ignore) {
+        return true;
+    }
+
+    // Root-specific patterns may also be written relative to the root
+    root.relative(path)
+        .map(|rel| should_ignore(rel, &root.dir.ignore))
+        .unwrap_or(false)
+}
+
+// Whether a change concerns the command according to its own `watch` and `ignore` patterns.
+// Patterns match the path as reported or relative to its watch root.
+pub fn is_relevant(path: &Path, root: &WatchRoot, command: &config::CommandConfig) -> bool {
+    let relative = root.relative(path).unwrap_or(path);
+    let matches = |patterns: &[String]| {
+        matches_patterns(path, patterns) || matches_patterns(relative, patterns)
+    };
+
+    if command.ignore.as_deref().is_some_and(matches) {
+        return false;
+    }
+    match command.watch.as_deref() {
+        Some(watch) => matches(watch),
+        None => true,
+    }
+}
+
+fn make_clickable(path: &Path, root: &WatchRoot) -> String {
     let path_str = path.to_str().unwrap_or("");
     let canonical_path = path.canonicalize().unwrap_or(path.to_path_buf());
-    let current_dir = env::current_dir().unwrap_or_default();
 
-    // Get relative path from current directory
-    let relative_path = path
-        .strip_prefix(&current_dir)
+    // Get relative path from the watch root
+    let relative_path = root
+        .relative(path)
         .unwrap_or(path)
         .to_str()
         .unwrap_or(path_str);
@@ -214,20 +304,168 @@ fn make_clickable(path: &Path) -> String {
         "\x1b]8;;file://{}\x1b\\{} {}/{}\x1b]8;;\x1b\\",
         canonical_path.display(),
         icon,
-        current_dir
-            .file_name()
-            .unwrap_or_default()
-            .to_str()
-            .unwrap_or("."),
+        root.label(),
         relative_path
     )
 }
 
-pub fn run(config_path: &str) -> Result<()> {
-    info!("Config: {}", config_path);
+// Trailing-edge restart scheduling for a single command
+struct Schedule {
+    debounce: Duration,
+    delay: Duration,
+    last_event: Option<Instant>,
+    last_restart: Instant,
+    // Paths changed since the last restart, and the kinds of events seen
+    changed: Vec<PathBuf>,
+    kinds: Vec<&'static str>,
+}
 
-    // Load configuration
-    let config = config::read_config(config_path);
+impl Schedule {
+    fn new(debounce: Duration, delay: Duration) -> Self {
+        Schedule {
+            debounce,
+            delay,
+            last_event: None,
+            last_restart: Instant::now(),
+            changed: Vec::new(),
+            kinds: Vec::new(),
+        }
+    }
+
+    fn record(&mut self, now: Instant) {
+        self.last_event = Some(now);
+    }
+
+    // Due once writes have been quiet for `delay` and `debounce` has passed since the last restart
+    fn is_due(&self, now: Instant) -> bool {
+        match self.last_event {
+            Some(event) => {
+                now.duration_since(event) >= self.delay
+                    && now.duration_since(self.last_restart) >= self.debounce
+            }
+            None => false,
+        }
+    }
+
+    fn is_pending(&self) -> bool {
+        self.last_event.is_some()
+    }
+
+    fn restarted(&mut self, now: Instant) {
+        self.last_event = None;
+        self.last_restart = now;
+    }
+}
+
+// Short name of a file event, as passed on to commands
+fn event_kind(kind: &notify::EventKind) -> &'static str {
+    use notify::event::ModifyKind;
+    use notify::EventKind;
+
+    match kind {
+        EventKind::Create(_) => "create",
+        EventKind::Modify(ModifyKind::Name(_)) => "rename",
+        EventKind::Modify(_) => "modify",
+        EventKind::Remove(_) => "remove",
+        _ => "other",
+    }
+}
+
+// watchx writes its log files on every line of output, they never count as changes
+fn is_log_file(path: &Path, config: &config::Config) -> bool {
+    config
+        .logs
+        .as_ref()
+        .is_some_and(|logs| command::absolute(path).starts_with(command::absolute(Path::new(&logs.dir))))
+}
+
+// Queue a file event for every command, keeping the paths that were not ignored
+fn record_event(
+    event: &notify::Event,
+    roots: &[WatchRoot],
+    config: &config::Config,
+    schedules: &mut [Schedule],
+) {
+    // Reads don't change anything, and builds read every source file
+    if matches!(event.kind, notify::EventKind::Access(_)) {
+        return;
+    }
+
+    // Collect non-ignored changed paths
+    let changed_paths: Vec<_> = event
+        .paths
+        .iter()
+        .filter(|path| !is_log_file(path, config))
+        .filter_map(|path| find_root(roots, path).map(|root| (path, root)))
+        .filter(|(path, root)| !should_ignore_in_root(path, root, &config.ignore))
+        .collect();
+
+    // Every change is kept, even during a restart, so a follow-up restart picks it up.
+    // Only the commands the change is relevant to are scheduled.
+    let now = Instant::now();
+    let kind = event_kind(&event.kind);
+    for (command, schedule) in config.commands.iter().zip(schedules.iter_mut()) {
+        for (path, root) in &changed_paths {
+            if !is_relevant(path, root, command) {
+                continue;
+            }
+            schedule.record(now);
+            if !schedule.kinds.contains(&kind) {
+                schedule.kinds.push(kind);
+            }
+            if !schedule.changed.contains(path) {
+                schedule.changed.push(path.to_path_buf());
+            }
+        }
+    }
+}
+
+// Hand the keyboard to a command until Ctrl-] is pressed. A command on a pseudo-terminal
+// gets every key as typed, others get whole lines edited in watchx's terminal.
+fn attach_input(keyboard: &mut keys::Keyboard, process: &processes::Process) {
+    let mode = if process.config.tty && cfg!(unix) {
+        keys::Mode::Raw
+    } else {
+        keys::Mode::Lines
+    };
+    info!("Typing into {}, press Ctrl-] for keyboard controls", process.config.name);
+    keyboard.set_mode(mode);
+}
+
+// Run until watchx is told to stop, returning the process exit code
+pub fn run(args: &command::RunArgs) -> Result<i32> {
+    info!("Config: {}", args.config);
+
+    // Trap termination signals before any child is spawned
+    if let Err(e) = signals::install() {
+        warn!("Failed to install signal handlers: {}", e);
+    }
+
+    // Load configuration, letting the CLI override the global timings
+    let mut config = config::read_config(&args.config);
+    if args.debounce.is_some() {
+        config.debounce = args.debounce;
+    }
+    if args.delay.is_some() {
+        config.delay = args.delay;
+    }
+
+    // Open the log files before watching, creating their directory is no change
+    let mut processes: Vec<processes::Process> = config
+        .commands
+        .iter()
+        .cloned()
+        .map(processes::Process::new)
+        .collect();
+    if let Some(settings) = &config.logs {
+        for process in &mut processes {
+            match logs::Log::open(settings, &process.config.name) {
+                Ok(log) => process.log = Some(log),
+                Err(e) => warn!("Failed to open the log file of {}: {}", process.config.name, e),
+            }
+        }
+        info!("Logs: {}", settings.dir);
+    }
 
     // Channel to receive file change events
     let (tx, rx) = channel();
@@ -237,99 +475,225 @@ pub fn run(config_path: &str) -> Result<()> {
         .with_poll_interval(Duration::from_secs(1))
         .with_compare_contents(false);
     let mut watcher: RecommendedWatcher = Watcher::new(tx, watch_config)?;
-    let path = Path::new(&config.watch_dir);
-    watcher.watch(path, RecursiveMode::Recursive)?;
+    let roots: Vec<WatchRoot> = config.watch_roots().into_iter().map(WatchRoot::new).collect();
+    for root in &roots {
+        let mode = if root.dir.recursive {
+            RecursiveMode::Recursive
+        } else {
+            RecursiveMode::NonRecursive
+        };
+        watcher.watch(Path::new(&root.dir.path), mode)?;
+    }
 
-    // Execute initial commands
-    let mut children = command::execute(&config.commands, &config.env);
-    let port = config
-        .env
-        .get("PORT")
-        .unwrap_or(&"8080".to_string())
-        .parse::<u16>()
-        .unwrap_or(8080);
-
-    // Use atomic types for better performance
-    let last_changed = Arc::new(Mutex::new(Instant::now()));
-    let is_restarting = Arc::new(Mutex::new(false));
-    let debounce_time = Duration::from_secs(1);
-
-    info!("Watch: {}", config.watch_dir);
-    info!("Hot reload: 1s");
-
-    // Spawn a single thread for debouncing
-    let is_restarting_clone = Arc::clone(&is_restarting);
-    thread::spawn(move || loop {
-        thread::sleep(debounce_time);
-        if let Ok(mut flag) = is_restarting_clone.lock() {
-            *flag = false;
+    // Per-command trailing-edge scheduling
+    let mut schedules: Vec<Schedule> = config
+        .commands
+        .iter()
+        .map(|c| Schedule::new(config.debounce_for(c), config.delay_for(c)))
+        .collect();
+
+    // Changes are dropped while watching is paused from the keyboard
+    let paused = Cell::new(false);
+
+    // Keep watching while a build runs, a build is stale once its command has new changes
+    // or watchx is asked to stop
+    let cancel = |schedules: &mut [Schedule], index: usize| {
+        while let Ok(event) = rx.try_recv() {
+            if let (Ok(event), false) = (event, paused.get()) {
+                record_event(&event, &roots, &config, schedules);
+            }
         }
+        signals::received().is_some() || schedules[index].is_pending()
+    };
+
+    // Take over the keyboard before any command starts, so none of them reads it
+    let mut keyboard = keys::listen();
+    // Where typed input goes, the `stdin: true` command to begin with
+    let mut input = config.commands.iter().position(|command| command.stdin);
+
+    // Execute initial commands
+    processes::start_all(&mut processes, &config, &mut |index| {
+        cancel(&mut schedules, index)
     });
 
-    let mut last_warn_time = Instant::now();
+    for root in &roots {
+        if root.dir.recursive {
+            info!("Watch: {}", root.dir.path);
+        } else {
+            info!("Watch: {} (non-recursive)", root.dir.path);
+        }
+    }
+    info!(
+        "Hot reload: debounce {}ms, delay {}ms",
+        config.debounce.unwrap_or(config::DEFAULT_DEBOUNCE_MS),
+        config.delay.unwrap_or(config::DEFAULT_DELAY_MS)
+    );
+    if let Some(keyboard) = keyboard.as_mut() {
+        info!("Keys: {}", keys::HELP);
+        if let Some(index) = input {
+            attach_input(keyboard, &processes[index]);
+        }
+    }
 
     // Use a timeout for the receiver to prevent blocking indefinitely
     let timeout = Duration::from_millis(100);
+    let exit_code = 'watch: loop {
+        if let Some(signal) = signals::received() {
+            info!("Received {}, shutting down...", signals::name(signal));
+            break signals::exit_code(signal);
+        }
 
-    loop {
-        // Use a timeout to prevent blocking indefinitely
-        match rx.recv_timeout(timeout) {
-            Ok(Ok(event)) => {
-                // Collect non-ignored changed paths
-                let changed_paths: Vec<_> = event
-                    .paths
-                    .iter()
-                    .filter(|path| !should_ignore(path, &config.ignore))
-                    .collect();
-
-                if changed_paths.is_empty() {
+        // Commands restarted from the keyboard
+        let mut requested: Vec<usize> = Vec::new();
+        while let Some(keyboard) = keyboard.as_mut() {
+            let Some(event) = keyboard.next() else {
+                break;
+            };
+            let key = match event {
+                keys::Event::Key(key) => key,
+                keys::Event::Input(bytes) => {
+                    if let Some(index) = input {
+                        processes::send_input(&mut processes[index], &bytes);
+                    }
                     continue;
                 }
-
-                let now = Instant::now();
-                let mut last_changed_time = last_changed.lock().unwrap();
-                let mut is_restarting_flag = is_restarting.lock().unwrap();
-
-                // Only restart if not already restarting and outside debounce period
-                if !*is_restarting_flag && now.duration_since(*last_changed_time) > debounce_time {
-                    info!("Changed:");
-                    for path in changed_paths {
-                        info!("{}", make_clickable(path));
+                keys::Event::Eof => {
+                    if let Some(index) = input {
+                        processes::close_input(&mut processes[index]);
                     }
-                    *is_restarting_flag = true;
-                    *last_changed_time = now;
-                    last_warn_time = now; // Reset warning time when changes are processed
-
-                    // Single restart for all changed files
-                    processes::restart(&mut children, &config.commands, &config.env, port);
-                } else if now.duration_since(last_warn_time) > debounce_time {
-                    // Show warning only if enough time has passed since last warning
-                    warn!("Changes detected but skipping reload (debounce or restart in progress)");
-                    last_warn_time = now;
+                    continue;
+                }
+                keys::Event::Detach => {
+                    keyboard.set_mode(keys::Mode::Keys);
+                    info!("Keys: {}", keys::HELP);
+                    continue;
+                }
+            };
+            match key {
+                keys::Key::Quit => {
+                    info!("Quitting...");
+                    break 'watch 0;
+                }
+                keys::Key::RestartAll => requested = (0..processes.len()).collect(),
+                keys::Key::Restart(index) if index < processes.len() => {
+                    if !requested.contains(&index) {
+                        requested.push(index);
+                    }
+                }
+                keys::Key::Restart(index) => warn!("There is no command {}", index + 1),
+                keys::Key::Pause => {
+                    paused.set(!paused.get());
+                    if paused.get() {
+                        info!("Paused watching, press p to resume");
+                    } else {
+                        info!("Resumed watching");
+                    }
+                }
+                keys::Key::Clear => output::clear_screen(),
+                keys::Key::List => processes::list(&mut processes),
+                keys::Key::Attach | keys::Key::AttachNext if processes.is_empty() => {}
+                keys::Key::Attach => {
+                    let index = *input.get_or_insert(0);
+                    attach_input(keyboard, &processes[index]);
+                }
+                keys::Key::AttachNext => {
+                    let index = input.map(|index| (index + 1) % processes.len()).unwrap_or(0);
+                    input = Some(index);
+                    attach_input(keyboard, &processes[index]);
+                }
+            }
+        }
+
+        // Use a timeout to prevent blocking indefinitely
+        match rx.recv_timeout(timeout) {
+            Ok(Ok(event)) => {
+                if !paused.get() {
+                    record_event(&event, &roots, &config, &mut schedules);
                 }
             }
             Ok(Err(e)) => {
                 error!("Watch error: {:?}", e);
             }
-            Err(std::sync::mpsc::RecvTimeoutError::Timeout) => {
-                // Timeout occurred, continue the loop
-                continue;
-            }
+            Err(std::sync::mpsc::RecvTimeoutError::Timeout) => {}
             Err(e) => {
                 error!("Channel error: {:?}", e);
                 // If the channel is closed, exit the loop
-                break;
+                break 1;
             }
         }
-    }
 
-    Ok(())
+        if signals::take_resized() {
+            processes::resize_terminals(&processes, &config);
+        }
+
+        // Report exits first so finished tasks are accounted for before they run again.
+        // Hung commands are restarted along with the crashed ones.
+        processes::restart_unhealthy(&mut processes);
+        processes::supervise(&mut processes, &config);
+
+        // Restart the commands whose changes have settled
+        let now = Instant::now();
+        // A task still running keeps its changes queued until it finishes
+        let due: Vec<usize> = (0..processes.len())
+            .filter(|&index| schedules[index].is_due(now) && !processes[index].is_running_task())
+            .collect();
+
+        if !due.is_empty() {
+            // Each command is told about its own changes
+            let mut changed: Vec<PathBuf> = Vec::new();
+            for &index in &due {
+                let schedule = &mut schedules[index];
+                for kind in schedule.kinds.drain(..) {
+                    processes[index].trigger.add(kind, &schedule.changed);
+                }
+                for path in schedule.changed.drain(..) {
+                    if !changed.contains(&path) {
+                        changed.push(path);
+                    }
+                }
+            }
+            info!("Changed:");
+            for path in &changed {
+                if let Some(root) = find_root(&roots, path) {
+                    info!("{}", make_clickable(path, root));
+                }
+            }
+            // Changes arriving from here on are queued for the next round
+            let since = due
+                .iter()
+                .filter_map(|&index| schedules[index].last_event)
+                .min()
+                .unwrap_or(now);
+            for &index in &due {
+                schedules[index].restarted(now);
+            }
+            processes::restart(&mut processes, &due, &config, since, &mut |index| {
+                cancel(&mut schedules, index)
+            });
+        }
+
+        if !requested.is_empty() {
+            let names: Vec<&str> = requested
+                .iter()
+                .map(|&index| processes[index].config.name.as_str())
+                .collect();
+            info!("Restart requested: {}", names.join(", "));
+            for &index in &requested {
+                processes[index].trigger.add("manual", &[]);
+            }
+            processes::restart(&mut processes, &requested, &config, Instant::now(), &mut |index| {
+                cancel(&mut schedules, index)
+            });
+        }
+    };
+
+    processes::shutdown(&mut processes);
+    Ok(exit_code)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::path::PathBuf;
 
     #[test]
     fn test_should_ignore_tilde_files() {
@@ -405,4 +769,106 @@ mod tests {
         assert!(should_ignore(&PathBuf::from(".git/config"), &patterns));
         assert!(!should_ignore(&PathBuf::from("src/main.rs"), &patterns));
     }
+
+    #[test]
+    fn test_find_root_prefers_most_specific() {
+        let roots = vec![
+            WatchRoot::new(config::WatchDir {
+                path: String::from("./"),
+                recursive: true,
+                ignore: None,
+            }),
+            WatchRoot::new(config::WatchDir {
+                path: String::from("./proto"),
+                recursive: true,
+                ignore: Some(vec![String::from("gen/")]),
+            }),
+        ];
+
+        let root = find_root(&roots, Path::new("./proto/user.proto")).unwrap();
+        assert_eq!(root.dir.path, "./proto");
+        let root = find_root(&roots, Path::new("./src/main.rs")).unwrap();
+        assert_eq!(root.dir.path, "./");
+        assert!(find_root(&roots, Path::new("/elsewhere/file.rs")).is_none());
+
+        // Root-specific patterns apply relative to the root
+        assert!(should_ignore_in_root(
+            Path::new("./proto/gen/user.pb.go"),
+            &roots[1],
+            &None
+        ));
+        assert!(!should_ignore_in_root(
+            Path::new("./proto/user.proto"),
+            &roots[1],
+            &None
+        ));
+    }
+
+    #[test]
+    fn test_schedule_waits_for_quiet_period() {
+        let start = Instant::now();
+        let mut schedule = Schedule::new(Duration::from_millis(0), Duration::from_millis(300));
+        assert!(!schedule.is_due(start));
+
+        schedule.record(start);
+        assert!(!schedule.is_due(start + Duration::from_millis(200)));
+
+        // A later write pushes the restart out again
+        schedule.record(start + Duration::from_millis(200));
+        assert!(!schedule.is_due(start + Duration::from_millis(400)));
+        assert!(schedule.is_due(start + Duration::from_millis(500)));
+
+        schedule.restarted(start + Duration::from_millis(500));
+        assert!(!schedule.is_due(start + Duration::from_millis(900)));
+    }
+
+    #[test]
+    fn test_schedule_defers_changes_within_debounce() {
+        let start = Instant::now();
+        let mut schedule = Schedule::new(Duration::from_millis(1000), Duration::from_millis(0));
+        schedule.restarted(start);
+
+        // A change right after a restart is queued rather than dropped
+        schedule.record(start + Duration::from_millis(100));
+        assert!(!schedule.is_due(start + Duration::from_millis(500)));
+        assert!(schedule.is_due(start + Duration::from_millis(1000)));
+    }
+
+    #[test]
+    fn test_is_relevant_routes_by_command_patterns() {
+        let root = WatchRoot::new(config::WatchDir {
+            path: "./".to_string(),
+            recursive: true,
+            ignore: None,
+        });
+        let command = |yaml: &str| {
+            config::parse_config(&format!("commands:\n  - {}", yaml))
+                .unwrap()
+                .commands
+                .remove(0)
+        };
+
+        let api = command(r#"{ command: "go run .", watch: ["**/*.go"], ignore: ["**/*_test.go"] }"#);
+        assert!(is_relevant(Path::new("./cmd/main.go"), &root, &api));
+        assert!(!is_relevant(Path::new("./web/app.css"), &root, &api));
+        assert!(!is_relevant(Path::new("./cmd/main_test.go"), &root, &api));
+
+        let web = command(r#"{ command: "npm run dev", watch: ["web/"] }"#);
+        assert!(is_relevant(Path::new("./web/src/app.css"), &root, &web));
+        assert!(!is_relevant(Path::new("./cmd/main.go"), &root, &web));
+
+        let everything = command(r#""cargo run""#);
+        assert!(is_relevant(Path::new("./anything.txt"), &root, &everything));
+    }
+
+    #[test]
+    fn test_log_files_are_never_changes() {
+        let config = config::parse_config("commands: [\"go run .\"]").unwrap();
+        assert!(is_log_file(Path::new("./.watchx/logs/api.log"), &config));
+        assert!(is_log_file(&env::current_dir().unwrap().join(".watchx/logs/api.log.1"), &config));
+        assert!(!is_log_file(Path::new("./src/main.go"), &config));
+
+        let config = config::parse_config("logs: false\ncommands: [\"go run .\"]").unwrap();
+        assert!(!is_log_file(Path::new("./.watchx/logs/api.log"), &config));
+    }
 }